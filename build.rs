@@ -1,6 +1,120 @@
+use flate2::{write::DeflateEncoder, Compression};
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+// Feature folders in src/ and the table each one becomes, every
+// `.material.bin` in a folder replaces `renderer/materials/<same name>`
+const MATERIAL_DIRS: [(&str, &str); 2] = [
+    ("no_fog_materials", "NO_FOG_MATERIALS"),
+    ("java_cubemap", "JAVA_CUBEMAP_MATERIALS"),
+];
+
+// Every file in here becomes a static named after it, `skins.json` is `SKINS_JSON`
+const ASSETS_DIR: &str = "assets";
+
 fn main() {
     cc::Build::new()
         .cpp(true)
         .file("src/string.cpp")
         .compile("stringstub");
+    generate_embedded().expect("Failed to generate embedded assets");
+}
+
+struct Generator {
+    out_dir: PathBuf,
+    code: Vec<u8>,
+    raw_total: usize,
+    stored_total: usize,
+}
+
+fn generate_embedded() -> io::Result<()> {
+    let src = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("src");
+    let mut generator = Generator {
+        out_dir: PathBuf::from(env::var("OUT_DIR").unwrap()),
+        code: Vec::new(),
+        raw_total: 0,
+        stored_total: 0,
+    };
+    for (folder, table) in MATERIAL_DIRS {
+        let dir = src.join(folder);
+        println!("cargo:rerun-if-changed={}", dir.display());
+        let files = files_in(&dir, ".material.bin")?;
+        writeln!(generator.code, "pub static {table}: [Embedded; {}] = [", files.len())?;
+        for file in files {
+            let name = file_name(&file);
+            let entry = generator.entry(&file, &format!("renderer/materials/{name}"), &format!("{folder}_{name}"))?;
+            writeln!(generator.code, "    {entry},")?;
+        }
+        writeln!(generator.code, "];")?;
+    }
+
+    let dir = src.join(ASSETS_DIR);
+    println!("cargo:rerun-if-changed={}", dir.display());
+    for file in files_in(&dir, "")? {
+        let name = file_name(&file);
+        let ident: String = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect();
+        let entry = generator.entry(&file, &name, &name)?;
+        writeln!(generator.code, "pub static {ident}: Embedded = {entry};")?;
+    }
+
+    println!(
+        "cargo:warning=embedded assets: {} bytes stored as {} bytes",
+        generator.raw_total, generator.stored_total
+    );
+    fs::write(generator.out_dir.join("embedded.rs"), generator.code)
+}
+
+impl Generator {
+    // Deflates one file into OUT_DIR as `stored_name` and returns the `Embedded::new`
+    // expression, files that don't shrink (like png) are included as they are
+    fn entry(&mut self, file: &Path, path: &str, stored_name: &str) -> io::Result<String> {
+        let raw = fs::read(file)?;
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&raw)?;
+        let deflated = encoder.finish()?;
+
+        let (stored, is_deflated) = if deflated.len() < raw.len() {
+            let stored = self.out_dir.join(format!("{stored_name}.deflate"));
+            fs::write(&stored, &deflated)?;
+            (stored, true)
+        } else {
+            (file.to_owned(), false)
+        };
+        let stored_len = if is_deflated { deflated.len() } else { raw.len() };
+        self.raw_total += raw.len();
+        self.stored_total += stored_len;
+        println!(
+            "cargo:warning=embedded {path}: {} -> {stored_len} bytes ({}% saved)",
+            raw.len(),
+            (raw.len() - stored_len) * 100 / raw.len().max(1)
+        );
+        Ok(format!(
+            "Embedded::new({path:?}, include_bytes!({:?}), {}, {is_deflated})",
+            stored.display().to_string(),
+            raw.len()
+        ))
+    }
+}
+
+fn files_in(dir: &Path, suffix: &str) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && file_name(&path).ends_with(suffix) {
+            files.push(path);
+        }
+    }
+    // Keep the generated code stable between builds
+    files.sort();
+    Ok(files)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap().to_string_lossy().into_owned()
 }
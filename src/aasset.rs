@@ -1,605 +1,402 @@
+use crate::archives::read_archive_entry;
+use crate::asset_data::AssetData;
+use crate::asset_path::AssetPath;
+use crate::config::get_config;
+use crate::dump;
+use crate::fake_asset::FakeAsset;
+use crate::game_version;
+use crate::guard::{self, Decision};
+use crate::hashing::{sha256, sha256_hex};
+use crate::json_patch::{apply_json_patches, has_json_patches, merge_replacement};
+use crate::material_cache::{self, Cached};
+use crate::material_inspect;
+use crate::material_patch;
+use crate::overrides::read_override;
+use crate::patch_cache;
+use crate::rules::{self, Action, AssetRule, PackMapping, PatchFn, Source};
+use crate::shader_patch;
+use crate::shader_strip;
+use crate::tracer::{self, Served, TraceEvent};
 use crate::ResourceLocation;
-use crate::config::{is_no_hurt_cam_enabled, is_no_fog_enabled, is_java_cubemap_enabled, is_particles_disabler_enabled, is_java_clouds_enabled, is_classic_skins_enabled, is_threed_skin_layer_enabled, is_cape_physics_enabled};
 use libc::{off64_t, off_t};
 use materialbin::{CompiledMaterialDefinition, MinecraftVersion};
-use ndk::asset::Asset;
 use ndk_sys::{AAsset, AAssetManager};
-use once_cell::sync::Lazy;
 use scroll::Pread;
-use serde_json::{Value, Map};
 use std::{
     borrow::Cow,
-    collections::HashMap,
-    ffi::{CStr, CString, OsStr},
-    io::{self, Cursor, Read, Seek, Write},
-    os::unix::ffi::OsStrExt,
+    ffi::{CStr, CString},
+    io::Write,
+    os::{fd::IntoRawFd, unix::ffi::OsStrExt},
     path::{Path, PathBuf},
-    sync::{Mutex, OnceLock},
+    sync::Arc,
+    time::Instant,
 };
 
-#[derive(PartialEq, Eq, Hash)]
-struct AAssetPtr(*const ndk_sys::AAsset);
-unsafe impl Send for AAssetPtr {}
-
-static MC_VERSION: OnceLock<Option<MinecraftVersion>> = OnceLock::new();
-
-static WANTED_ASSETS: Lazy<Mutex<HashMap<AAssetPtr, Cursor<Vec<u8>>>>> =
-    Lazy::new(|| Mutex::new(HashMap::new()));
-
-const LEGACY_CUBEMAP_MATERIAL_BIN: &[u8] = include_bytes!("assets/java_cubemap/LegacyCubemap.material.bin");
-const RENDER_CHUNK_MATERIAL_BIN: &[u8] = include_bytes!("assets/no_fog_materials/RenderChunk.material.bin");
-
-const CUSTOM_SPLASHES_JSON: &str = r#"{"splashes":["Origin Client","Origin > any other client","The Best Client!!","BlueCat","Origin is so much better","Origin Optimizes like no other client","Make Sure to star our repository:https://github.com/Origin-Client/Origin","Contributions open!","Made by the community, for the community","Yami is goated!!"]}"#;
-
-// Cape geometry data as JSON Value for easy insertion
-const CUSTOM_CAPE_GEOMETRY_JSON: &str = r#"{"format_version":"1.12.0","minecraft:geometry":[{"description":{"identifier":"geometry.cape","texture_width":64,"texture_height":32,"visible_bounds_width":2,"visible_bounds_height":3.5,"visible_bounds_offset":[0,1.25,0]},"bones":[{"name":"root","pivot":[0,0,0]},{"name":"waist","parent":"root","pivot":[0,12,0]},{"name":"body","parent":"waist","pivot":[0,24,0]},{"name":"cape","parent":"body","pivot":[0,24,2],"rotation":[0,180,0]},{"name":"part1","parent":"cape","pivot":[0,24,2],"cubes":[{"origin":[-5,23,1],"size":[10,1,1],"uv":{"north":{"uv":[1,1],"uv_size":[10,1]},"east":{"uv":[0,1],"uv_size":[1,1]},"south":{"uv":[12,1],"uv_size":[10,1]},"west":{"uv":[11,1],"uv_size":[1,1]},"up":{"uv":[1,1],"uv_size":[10,-1]}}}]},{"name":"part2","parent":"part1","pivot":[0,23,1],"cubes":[{"origin":[-5,22,1],"size":[10,1.5,1],"uv":{"north":{"uv":[1,1.5],"uv_size":[10,1.5]},"east":{"uv":[0,1.5],"uv_size":[1,1.5]},"south":{"uv":[12,1.5],"uv_size":[10,1.5]},"west":{"uv":[11,1.5],"uv_size":[1,1.5]}}}]},{"name":"part3","parent":"part2","pivot":[0,22,1],"cubes":[{"origin":[-5,21,1],"size":[10,1.5,1],"uv":{"north":{"uv":[1,2.5],"uv_size":[10,1.5]},"east":{"uv":[0,2.5],"uv_size":[1,1.5]},"south":{"uv":[12,2.5],"uv_size":[10,1.5]},"west":{"uv":[11,2.5],"uv_size":[1,1.5]}}}]},{"name":"part4","parent":"part3","pivot":[0,21,1],"cubes":[{"origin":[-5,20,1],"size":[10,1.5,1],"uv":{"north":{"uv":[1,3.5],"uv_size":[10,1.5]},"east":{"uv":[0,3.5],"uv_size":[1,1.5]},"south":{"uv":[12,3.5],"uv_size":[10,1.5]},"west":{"uv":[11,3.5],"uv_size":[1,1.5]}}}]},{"name":"part5","parent":"part4","pivot":[0,20,1],"cubes":[{"origin":[-5,19,1],"size":[10,1.5,1],"uv":{"north":{"uv":[1,4.5],"uv_size":[10,1.5]},"east":{"uv":[0,4.5],"uv_size":[1,1.5]},"south":{"uv":[12,4.5],"uv_size":[10,1.5]},"west":{"uv":[11,4.5],"uv_size":[1,1.5]}}}]},{"name":"part6","parent":"part5","pivot":[0,19,1],"cubes":[{"origin":[-5,18,1],"size":[10,1.5,1],"uv":{"north":{"uv":[1,5.5],"uv_size":[10,1.5]},"east":{"uv":[0,5.5],"uv_size":[1,1.5]},"south":{"uv":[12,5.5],"uv_size":[10,1.5]},"west":{"uv":[11,5.5],"uv_size":[1,1.5]}}}]},{"name":"part7","parent":"part6","pivot":[0,18,1],"cubes":[{"origin":[-5,17,1],"size":[10,1.5,1],"uv":{"north":{"uv":[1,6.5],"uv_size":[10,1.5]},"east":{"uv":[0,6.5],"uv_size":[1,1.5]},"south":{"uv":[12,6.5],"uv_size":[10,1.5]},"west":{"uv":[11,6.5],"uv_size":[1,1.5]}}}]},{"name":"part8","parent":"part7","pivot":[0,17,1],"cubes":[{"origin":[-5,16,1],"size":[10,1.5,1],"uv":{"north":{"uv":[1,7.5],"uv_size":[10,1.5]},"east":{"uv":[0,7.5],"uv_size":[1,1.5]},"south":{"uv":[12,7.5],"uv_size":[10,1.5]},"west":{"uv":[11,7.5],"uv_size":[1,1.5]}}}]},{"name":"part9","parent":"part8","pivot":[0,16,1],"cubes":[{"origin":[-5,15,1],"size":[10,1.5,1],"uv":{"north":{"uv":[1,8.5],"uv_size":[10,1.5]},"east":{"uv":[0,8.5],"uv_size":[1,1.5]},"south":{"uv":[12,8.5],"uv_size":[10,1.5]},"west":{"uv":[11,8.5],"uv_size":[1,1.5]}}}]},{"name":"part10","parent":"part9","pivot":[0,15,1],"cubes":[{"origin":[-5,14,1],"size":[10,1.5,1],"uv":{"north":{"uv":[1,9.5],"uv_size":[10,1.5]},"east":{"uv":[0,9.5],"uv_size":[1,1.5]},"south":{"uv":[12,9.5],"uv_size":[10,1.5]},"west":{"uv":[11,9.5],"uv_size":[1,1.5]}}}]},{"name":"part11","parent":"part10","pivot":[0,14,1],"cubes":[{"origin":[-5,13,1],"size":[10,1.5,1],"uv":{"north":{"uv":[1,10.5],"uv_size":[10,1.5]},"east":{"uv":[0,10.5],"uv_size":[1,1.5]},"south":{"uv":[12,10.5],"uv_size":[10,1.5]},"west":{"uv":[11,10.5],"uv_size":[1,1.5]}}}]},{"name":"part12","parent":"part11","pivot":[0,13,1],"cubes":[{"origin":[-5,12,1],"size":[10,1.5,1],"uv":{"north":{"uv":[1,11.5],"uv_size":[10,1.5]},"east":{"uv":[0,11.5],"uv_size":[1,1.5]},"south":{"uv":[12,11.5],"uv_size":[10,1.5]},"west":{"uv":[11,11.5],"uv_size":[1,1.5]}}}]},{"name":"part13","parent":"part12","pivot":[0,12,1],"cubes":[{"origin":[-5,11,1],"size":[10,1.5,1],"uv":{"north":{"uv":[1,12.5],"uv_size":[10,1.5]},"east":{"uv":[0,12.5],"uv_size":[1,1.5]},"south":{"uv":[12,12.5],"uv_size":[10,1.5]},"west":{"uv":[11,12.5],"uv_size":[1,1.5]}}}]},{"name":"part14","parent":"part13","pivot":[0,11,1],"cubes":[{"origin":[-5,10,1],"size":[10,1.5,1],"uv":{"north":{"uv":[1,13.5],"uv_size":[10,1.5]},"east":{"uv":[0,13.5],"uv_size":[1,1.5]},"south":{"uv":[12,13.5],"uv_size":[10,1.5]},"west":{"uv":[11,13.5],"uv_size":[1,1.5]}}}]},{"name":"part15","parent":"part14","pivot":[0,10,1],"cubes":[{"origin":[-5,9,1],"size":[10,1.5,1],"uv":{"north":{"uv":[1,14.5],"uv_size":[10,1.5]},"east":{"uv":[0,14.5],"uv_size":[1,1.5]},"south":{"uv":[12,14.5],"uv_size":[10,1.5]},"west":{"uv":[11,14.5],"uv_size":[1,1.5]}}}]},{"name":"part16","parent":"part15","pivot":[0,9,1],"cubes":[{"origin":[-5,8,1],"size":[10,1.5,1],"uv":{"north":{"uv":[1,15.5],"uv_size":[10,1.5]},"east":{"uv":[0,15.5],"uv_size":[1,1.5]},"south":{"uv":[12,15.5],"uv_size":[10,1.5]},"west":{"uv":[11,15.5],"uv_size":[1,1.5]},"down":{"uv":[11,1],"uv_size":[10,-1]}}}]}]}]}"#;
-
-const CUSTOM_CAPE_ANIMATION_JSON: &str = r#"{"format_version":"1.8.0","animations":{"animation.player.cape":{"loop":true,"bones":{"cape":{"rotation":["math.clamp(math.lerp(0, -110, query.cape_flap_amount) - (13 * query.modified_move_speed), -70, 0)","query.modified_move_speed * math.pow(math.sin(query.body_y_rotation - query.head_y_rotation(0)), 3) * 55",0],"position":[0,0,"query.get_root_locator_offset('armor_offset.default_neck', 1)"]},"part1":{"rotation":["math.clamp(query.cape_flap_amount, 0, 0.5) * (math.cos(query.modified_distance_moved * 18) * 16)",0,"0"]},"part2":{"rotation":["math.clamp(query.cape_flap_amount, 0, 0.5) * math.cos(22 - query.modified_distance_moved * 18) * 13",0,0],"scale":1},"part3":{"rotation":["math.clamp(query.cape_flap_amount, 0, 0.5) * math.cos(50 - query.modified_distance_moved * 18) * 13",0,0]},"part4":{"rotation":["math.clamp(query.cape_flap_amount, 0, 0.5) * math.cos(76 - query.modified_distance_moved * 18) * 13",0,0]},"part5":{"rotation":["math.clamp(query.cape_flap_amount, 0, 0.5) * math.cos(100 - query.modified_distance_moved * 18) * 13",0,0]},"part6":{"rotation":["math.clamp(query.cape_flap_amount, 0, 0.5) * math.cos(122 - query.modified_distance_moved * 18) * 13",0,0]},"part7":{"rotation":["math.clamp(query.cape_flap_amount, 0, 0.5) * math.cos(142 - query.modified_distance_moved * 18) * 13",0,0]},"part8":{"rotation":["math.clamp(query.cape_flap_amount, 0, 0.5) * math.cos(160 - query.modified_distance_moved * 18) * 13",0,0]},"part9":{"rotation":["math.clamp(query.cape_flap_amount, 0, 0.5) * math.cos(176 - query.modified_distance_moved * 18) * 13",0,0]},"part10":{"rotation":["math.clamp(query.cape_flap_amount, 0, 0.5) * math.cos(190 - query.modified_distance_moved * 18) * 13",0,0]},"part11":{"rotation":["math.clamp(query.cape_flap_amount, 0, 0.5) * math.cos(202 - query.modified_distance_moved * 18) * 13",0,0]},"part12":{"rotation":["math.clamp(query.cape_flap_amount, 0, 0.5) * math.cos(212 - query.modified_distance_moved * 18) * 13",0,0]},"part13":{"rotation":["math.clamp(query.cape_flap_amount, 0, 0.5) * math.cos(220 - query.modified_distance_moved * 18) * 13",0,0]},"part14":{"rotation":["math.clamp(query.cape_flap_amount, 0, 0.5) * math.cos(226 - query.modified_distance_moved * 18) * 13",0,0]},"part15":{"rotation":["math.clamp(query.cape_flap_amount, 0, 0.5) * math.cos(230 - query.modified_distance_moved * 18) * 13",0,0]},"part16":{"rotation":["math.clamp(query.cape_flap_amount, 0, 0.5) * math.cos(232 - query.modified_distance_moved * 18) * 13",0,0]},"shoulders":{"rotation":[0,"query.modified_move_speed * math.pow(math.sin(query.body_y_rotation - query.head_y_rotation(0)), 3) * 60",0]}}}}}"#;
-
-const CUSTOM_FIRST_PERSON_JSON: &str = r#"{"format_version":"1.18.10","minecraft:camera_entity":{"description":{"identifier":"minecraft:first_person"},"components":{"minecraft:camera":{"field_of_view":66,"near_clipping_plane":0.025,"far_clipping_plane":2500},"minecraft:camera_first_person":{},"minecraft:camera_render_first_person_objects":{},"minecraft:camera_attach_to_player":{},"minecraft:camera_offset":{"view":[0,0],"entity":[0,0,0]},"minecraft:camera_direct_look":{"pitch_min":-89.9,"pitch_max":89.9},"minecraft:camera_perspective_option":{"view_mode":"first_person"},"minecraft:update_player_from_camera":{"look_mode":"along_camera"},"minecraft:extend_player_rendering":{},"minecraft:camera_player_sleep_vignette":{},"minecraft:vr_comfort_move":{},"minecraft:default_input_camera":{},"minecraft:gameplay_affects_fov":{},"minecraft:allow_inside_block":{}}}}"#;
-const CUSTOM_THIRD_PERSON_JSON: &str = r#"{"format_version":"1.18.10","minecraft:camera_entity":{"description":{"identifier":"minecraft:third_person"},"components":{"minecraft:camera":{"field_of_view":66,"near_clipping_plane":0.025,"far_clipping_plane":2500},"minecraft:camera_third_person":{},"minecraft:camera_render_player_model":{},"minecraft:camera_attach_to_player":{},"minecraft:camera_offset":{"view":[0,0],"entity":[0,2,5]},"minecraft:camera_look_at_player":{},"minecraft:camera_orbit":{"azimuth_smoothing_spring":0,"polar_angle_smoothing_spring":0,"distance_smoothing_spring":0,"polar_angle_min":0.1,"polar_angle_max":179.9,"radius":4},"minecraft:camera_avoidance":{"relax_distance_smoothing_spring":0,"distance_constraint_min":0.25},"minecraft:camera_perspective_option":{"view_mode":"third_person"},"minecraft:update_player_from_camera":{"look_mode":"along_camera"},"minecraft:camera_player_sleep_vignette":{},"minecraft:gameplay_affects_fov":{},"minecraft:allow_inside_block":{},"minecraft:extend_player_rendering":{}}}}"#;
-const CUSTOM_THIRD_PERSON_FRONT_JSON: &str = r#"{"format_version":"1.18.10","minecraft:camera_entity":{"description":{"identifier":"minecraft:third_person_front"},"components":{"minecraft:camera":{"field_of_view":66,"near_clipping_plane":0.025,"far_clipping_plane":2500},"minecraft:camera_third_person":{},"minecraft:camera_render_player_model":{},"minecraft:camera_attach_to_player":{},"minecraft:camera_offset":{"view":[0,0],"entity":[0,2,5]},"minecraft:camera_look_at_player":{},"minecraft:camera_orbit":{"azimuth_smoothing_spring":0,"polar_angle_smoothing_spring":0,"distance_smoothing_spring":0,"polar_angle_min":0.1,"polar_angle_max":179.9,"radius":4,"invert_x_input":true},"minecraft:camera_avoidance":{"relax_distance_smoothing_spring":0,"distance_constraint_min":0.25},"minecraft:camera_perspective_option":{"view_mode":"third_person_front"},"minecraft:update_player_from_camera":{"look_mode":"at_camera"},"minecraft:camera_player_sleep_vignette":{},"minecraft:gameplay_affects_fov":{},"minecraft:allow_inside_block":{},"minecraft:extend_player_rendering":{}}}}"#;
-
-const CUSTOM_LOADING_MESSAGES_JSON: &str = r#"{"beginner_loading_messages":["Origin Client","Origin > any other client","The Best Client!!","BlueCat","Origin is so much better","Origin Optimizes like no other client","Make Sure to star our repository:https://github.com/Origin-Client/Origin","Contributions open!","Made by the community, for the community","Yami is goated!!"],"mid_game_loading_messages":["Origin Client","Origin > any other client","The Best Client!!","BlueCat","Origin is so much better","Origin Optimizes like no other client","Make Sure to star our repository:https://github.com/Origin-Client/Origin","Contributions open!","Made by the community, for the community","Yami is goated!!"],"late_game_loading_messages":["Origin Client","Origin > any other client","The Best Client!!","BlueCat","Origin is so much better","Origin Optimizes like no other client","Make Sure to star our repository:https://github.com/Origin-Client/Origin","Contributions open!","Made by the community, for the community","Yami is goated!!"],"creative_loading_messages":["Origin Client","Origin > any other client","The Best Client!!","BlueCat","Origin is so much better","Origin Optimizes like no other client","Make Sure to star our repository:https://github.com/Origin-Client/Origin","Contributions open!","Made by the community, for the community","Yami is goated!!"],"editor_loading_messages":["Origin Client","Origin > any other client","The Best Client!!","BlueCat","Origin is so much better","Origin Optimizes like no other client","Make Sure to star our repository:https://github.com/Origin-Client/Origin","Contributions open!","Made by the community, for the community","Yami is goated!!"],"realms_loading_messages":["Origin Client","Origin > any other client","The Best Client!!","BlueCat","Origin is so much better","Origin Optimizes like no other client","Make Sure to star our repository:https://github.com/Origin-Client/Origin","Contributions open!","Made by the community, for the community","Yami is goated!!"],"addons_loading_messages":["Origin Client","Origin > any other client","The Best Client!!","BlueCat","Origin is so much better","Origin Optimizes like no other client","Make Sure to star our repository:https://github.com/Origin-Client/Origin","Contributions open!","Made by the community, for the community","Yami is goated!!"],"store_progress_tooltips":["Origin Client","Origin > any other client","The Best Client!!","BlueCat","Origin is so much better","Origin Optimizes like no other client","Make Sure to star our repository:https://github.com/Origin-Client/Origin","Contributions open!","Made by the community, for the community","Yami is goated!!"]}"#;
-
-const CUSTOM_SKINS_JSON: &str = r#"{"skins":[{"localization_name":"Steve","geometry":"geometry.humanoid.custom","texture":"steve.png","type":"free"},{"localization_name":"Alex","geometry":"geometry.humanoid.customSlim","texture":"alex.png","type":"free"}],"serialize_name":"Standard","localization_name":"Standard"}"#;
-
-const CLASSIC_STEVE_TEXTURE: &[u8] = include_bytes!("assets/steve.png");
-const CLASSIC_ALEX_TEXTURE: &[u8] = include_bytes!("assets/alex.png");
-
-const JAVA_CLOUDS_TEXTURE: &[u8] = include_bytes!("assets/clouds.png");
-
-fn get_current_mcver(man: ndk::asset::AssetManager) -> Option<MinecraftVersion> {
-    let mut file = match get_uitext(man) {
-        Some(asset) => asset,
-        None => {
-            log::error!("Shader fixing is disabled as no mc version was found");
-            return None;
+pub(crate) unsafe fn open(
+    man: *mut AAssetManager,
+    fname: *const libc::c_char,
+    mode: libc::c_int,
+) -> *mut ndk_sys::AAsset {
+    let started = tracer::is_enabled().then(Instant::now);
+    let aasset = unsafe { ndk_sys::AAssetManager_open(man, fname, mode) };
+    let c_str = unsafe { CStr::from_ptr(fname) };
+    let Ok(raw_path) = c_str.to_str() else {
+        log::warn!("Path is not utf8: {c_str:?}");
+        if let Some(started) = started {
+            trace_open(&c_str.to_string_lossy(), mode, aasset, None, aasset, false, started);
         }
+        return aasset;
     };
-    let mut buf = Vec::with_capacity(file.length());
-    if let Err(e) = file.read_to_end(&mut buf) {
-        log::error!("Something is wrong with AssetManager, mc detection failed: {e}");
-        return None;
+    let Some(asset_path) = AssetPath::new(raw_path) else {
+        log::warn!("Path leaves the apk: {raw_path}");
+        if let Some(started) = started {
+            trace_open(raw_path, mode, aasset, None, aasset, false, started);
+        }
+        return aasset;
     };
-    for version in materialbin::ALL_VERSIONS {
-        if buf
-            .pread_with::<CompiledMaterialDefinition>(0, version)
-            .is_ok()
-        {
-            log::info!("Mc version is {version}");
-            return Some(version);
-        };
+    let rule = rules::find_rule(&asset_path, get_config(), !aasset.is_null());
+    let (result, patched_apk) = match rule {
+        Some(rule) => apply_rule(man, aasset, &asset_path, rule),
+        None => (aasset, false),
+    };
+    if let Some(started) = started {
+        trace_open(asset_path.as_str(), mode, aasset, rule, result, patched_apk, started);
     }
-    None
+    result
 }
 
-fn get_uitext(man: ndk::asset::AssetManager) -> Option<Asset> {
-    const NEW: &CStr = c"assets/renderer/materials/UIText.material.bin";
-    const OLD: &CStr = c"renderer/materials/UIText.material.bin";
-    for path in [NEW, OLD] {
-        if let Some(asset) = man.open(path) {
-            return Some(asset);
+// The handle to give the game, and whether it holds the apk file patched in
+// place of what the rule normally serves
+unsafe fn apply_rule(
+    man: *mut AAssetManager,
+    aasset: *mut AAsset,
+    asset_path: &AssetPath,
+    rule: &AssetRule,
+) -> (*mut AAsset, bool) {
+    let served = match rule.action {
+        Action::Block => {
+            log::info!("[{}] Blocking {}", rule.name, asset_path.as_str());
+            if dump::wants(asset_path) {
+                dump_intercepted(aasset, asset_path, rule, None);
+            }
+            if !aasset.is_null() {
+                ndk_sys::AAsset_close(aasset);
+            }
+            std::ptr::null_mut()
         }
-    }
-    None
-}
-
-macro_rules! folder_list {
-    ($( apk: $apk_folder:literal -> pack: $pack_folder:expr),
-        *,
-    ) => {
-        [
-            $(($apk_folder, $pack_folder)),*,
-        ]
-    }
+        Action::Replace(embedded) => {
+            let Some(data) = embedded.get() else {
+                log::error!("[{}] Embedded file is unusable, serving original", rule.name);
+                return (rewound(aasset), false);
+            };
+            // The guard compares what we ship, converting first would hide
+            // a version mismatch of the embedded material
+            let decision = guard::decide(
+                rule,
+                asset_path,
+                get_config(),
+                Some(data),
+                || read_original(aasset),
+            );
+            match decision {
+                Decision::Apply => {
+                    log::info!("[{}] Replacing {}", rule.name, asset_path.as_str());
+                    // Embedded materials are built for one version, like pack ones
+                    let data = if asset_path.file_name().ends_with(".material.bin") {
+                        process_material(man, asset_path.as_str(), data)
+                            .map_or(AssetData::Static(data), AssetData::from)
+                    } else {
+                        with_json_patches(asset_path, AssetData::Static(data))
+                    };
+                    serve(aasset, asset_path, rule, data)
+                }
+                Decision::Merge => {
+                    let merged = read_original(aasset)
+                        .ok_or_else(|| "cannot read the original".into())
+                        .and_then(|original| merge_replacement(&original, data));
+                    match merged {
+                        Ok(merged) => {
+                            log::info!("[{}] Merging into {}", rule.name, asset_path.as_str());
+                            serve(aasset, asset_path, rule, with_json_patches(asset_path, merged.into()))
+                        }
+                        Err(e) => {
+                            log::error!("[{}] Merge failed, serving original: {e}", rule.name);
+                            rewound(aasset)
+                        }
+                    }
+                }
+                Decision::Skip if has_json_patches(asset_path) => {
+                    return (patch_original(aasset, asset_path, rule, apply_json_patches), true);
+                }
+                Decision::Skip => rewound(aasset),
+            }
+        }
+        Action::Patch(patch) => {
+            let decision = guard::decide(rule, asset_path, get_config(), None, || read_original(aasset));
+            if decision == Decision::Skip {
+                return (rewound(aasset), false);
+            }
+            log::info!("[{}] Patching {}", rule.name, asset_path.as_str());
+            patch_original(aasset, asset_path, rule, patch)
+        }
+        Action::Override(ref source) => {
+            let buffer = match source {
+                Source::Folder => read_override(asset_path),
+                Source::Archive => read_archive_entry(asset_path),
+            };
+            let buffer = match buffer {
+                Ok(buffer) => buffer,
+                Err(e) => {
+                    log::error!("[{}] Failed to read override: {e}", rule.name);
+                    return (aasset, false);
+                }
+            };
+            log::info!("[{}] Overriding {}", rule.name, asset_path.as_str());
+            let buffer = if asset_path.file_name().ends_with(".material.bin") {
+                process_material(man, asset_path.as_str(), &buffer).unwrap_or(buffer)
+            } else {
+                buffer
+            };
+            serve(aasset, asset_path, rule, buffer.into())
+        }
+        Action::Redirect(_) => {
+            let Some((mapping, file)) = rule.redirect(asset_path) else {
+                return (aasset, false);
+            };
+            let Some(buffer) = load_from_packs(man, mapping, file) else {
+                // Not in any pack, the apk file may still need patching
+                if has_json_patches(asset_path) {
+                    return (patch_original(aasset, asset_path, rule, apply_json_patches), true);
+                }
+                return (process_apk_material(man, aasset, asset_path, rule), true);
+            };
+            if mapping.merge_json && file.ends_with(".json") {
+                let merged = read_original(aasset)
+                    .ok_or_else(|| "cannot read the original".into())
+                    .and_then(|original| merge_replacement(&original, &buffer));
+                match merged {
+                    Ok(merged) => {
+                        log::info!(
+                            "[{}] Merging pack file into {}",
+                            rule.name,
+                            asset_path.as_str()
+                        );
+                        serve(aasset, asset_path, rule, with_json_patches(asset_path, merged.into()))
+                    }
+                    Err(e) => {
+                        log::warn!("[{}] Merge failed, serving the pack file: {e}", rule.name);
+                        serve(aasset, asset_path, rule, with_json_patches(asset_path, buffer.into()))
+                    }
+                }
+            } else {
+                serve(aasset, asset_path, rule, with_json_patches(asset_path, buffer.into()))
+            }
+        }
+    };
+    (served, false)
 }
 
-fn get_no_fog_material_data(filename: &str) -> Option<&'static [u8]> {
-    if !is_no_fog_enabled() {
-        return None;
-    }
-    
-    match filename {
-        "RenderChunk.material.bin" => Some(RENDER_CHUNK_MATERIAL_BIN),
-        _ => None,
+// The apk file with `patch` run over it, or the file as it is when that fails
+unsafe fn patch_original(
+    aasset: *mut AAsset,
+    asset_path: &AssetPath,
+    rule: &AssetRule,
+    patch: PatchFn,
+) -> *mut AAsset {
+    let Some(original_data) = read_original(aasset) else {
+        log::error!("[{}] Failed to read original data", rule.name);
+        return rewound(aasset);
+    };
+    match cached_patch(asset_path, &original_data, patch) {
+        Ok(patched) => serve(aasset, asset_path, rule, patched.into()),
+        Err(e) => {
+            log::error!("[{}] Patch failed, serving original: {e}", rule.name);
+            rewound(aasset)
+        }
     }
 }
 
-fn get_java_cubemap_material_data(filename: &str) -> Option<&'static [u8]> {
-    if !is_java_cubemap_enabled() {
-        return None;
+// Json patches for a file some other rule replaced, they go on top of it
+fn with_json_patches(asset_path: &AssetPath, data: AssetData) -> AssetData {
+    if !has_json_patches(asset_path) {
+        return data;
     }
-    
-    match filename {
-        "LegacyCubemap.material.bin" => Some(LEGACY_CUBEMAP_MATERIAL_BIN),
-        _ => None,
+    match cached_patch(asset_path, data.as_ref(), apply_json_patches) {
+        Ok(patched) => {
+            log::info!("Patching the replacement of {}", asset_path.as_str());
+            patched.into()
+        }
+        Err(e) => {
+            log::error!("Patching the replacement of {} failed: {e}", asset_path.as_str());
+            data
+        }
     }
 }
 
-// Enhanced particles disabler - blocks entire particles folder and all particle-related files
-fn is_particles_folder_to_block(c_path: &Path) -> bool {
-    if !is_particles_disabler_enabled() {
-        return false;
-    }
-    
-    let path_str = c_path.to_string_lossy();
-    
-    // Block any file related to particles
-    let particle_patterns = [
-        "/particles/",
-        "particles/",
-        "/particle/",
-        "particle/",
-        "_particle",
-        "particle_",
-        ".particle.",
-        "particles.",
-        "/effects/",
-        "effects/",
-        /*"_effect",
-        "effect_",*/
-        ".effect.",
-        "effects.",
-    ];
-    
-    particle_patterns.iter().any(|pattern| {
-        path_str.contains(pattern)
-    }) || path_str.starts_with("particles") || path_str.ends_with(".particle") || path_str.ends_with("_particle.json")
-}
-
-// Enhanced clouds detection with more patterns
-fn is_clouds_texture_file(c_path: &Path) -> bool {
-    if !is_java_clouds_enabled() {
-        return false;
-    }
-    
-    let path_str = c_path.to_string_lossy();
-    
-    let cloud_patterns = [
-        "textures/environment/clouds.png",
-        "/textures/environment/clouds.png",
-        "environment/clouds.png",
-        "/environment/clouds.png",
-        "clouds.png",
-        "textures/clouds.png",
-        "/textures/clouds.png",
-        "resource_packs/vanilla/textures/environment/clouds.png",
-        "assets/resource_packs/vanilla/textures/environment/clouds.png",
-        "vanilla/textures/environment/clouds.png",
-    ];
-    
-    cloud_patterns.iter().any(|pattern| {
-        path_str.contains(pattern) || path_str.ends_with(pattern)
-    })
-}
-
-fn is_skin_file_path(c_path: &Path, filename: &str) -> bool {
-    let path_str = c_path.to_string_lossy();
-    
-    let possible_paths = [
-        format!("vanilla/{}", filename),
-        format!("skin_packs/vanilla/{}", filename),
-        format!("resource_packs/vanilla/{}", filename),
-        format!("assets/skin_packs/vanilla/{}", filename),
-    ];
-    
-    possible_paths.iter().any(|path| {
-        path_str.contains(path) || path_str.ends_with(path)
-    })
-}
-
-fn is_classic_skins_steve_texture_file(c_path: &Path) -> bool {
-    if !is_classic_skins_enabled() {
-        return false;
+fn cached_patch(
+    asset_path: &AssetPath,
+    data: &[u8],
+    patch: PatchFn,
+) -> Result<Arc<[u8]>, Box<dyn std::error::Error>> {
+    let hash = sha256(data);
+    if let Some(cached) = patch_cache::get(asset_path, &hash) {
+        return Ok(cached);
     }
-    
-    is_skin_file_path(c_path, "steve.png")
+    let limit = get_config().patch_cache_mb * 1024 * 1024;
+    Ok(patch_cache::insert(asset_path, hash, patch(asset_path, data)?, limit))
 }
 
-fn is_classic_skins_alex_texture_file(c_path: &Path) -> bool {
-    if !is_classic_skins_enabled() {
-        return false;
+// Apk materials only need work when they get stripped or patched
+unsafe fn process_apk_material(
+    man: *mut AAssetManager,
+    aasset: *mut AAsset,
+    asset_path: &AssetPath,
+    rule: &AssetRule,
+) -> *mut AAsset {
+    let name = asset_path.as_str();
+    let wanted = asset_path.file_name().ends_with(".material.bin")
+        && (get_config().strip_foreign_shaders
+            || shader_patch::has_patches(name)
+            || material_patch::has_patches(name));
+    if !wanted {
+        return aasset;
     }
-    
-    is_skin_file_path(c_path, "alex.png")
-}
-
-fn is_classic_skins_json_file(c_path: &Path) -> bool {
-    if !is_classic_skins_enabled() {
-        return false;
+    let Some(original) = read_original(aasset) else {
+        return rewound(aasset);
+    };
+    match process_material(man, name, &original) {
+        Some(processed) => serve(aasset, asset_path, rule, processed.into()),
+        None => rewound(aasset),
     }
-    
-    is_skin_file_path(c_path, "skins.json")
-}
-
-fn is_persona_file_to_block(c_path: &Path) -> bool {
-    if !is_classic_skins_enabled() {
-        return false;
-    }
-    
-    let path_str = c_path.to_string_lossy();
-    
-    let blocked_personas = [
-        "persona/08_Kai_Dcast.json",
-        "persona/07_Zuri_Dcast.json", 
-        "persona/06_Efe_Dcast.json",
-        "persona/05_Makena_Dcast.json",
-        "persona/04_Sunny_Dcast.json",
-        "persona/03_Ari_Dcast.json",
-        "persona/02_ Noor_Dcast.json", 
-    ];
-    
-    blocked_personas.iter().any(|persona_path| {
-        path_str.contains(persona_path) || path_str.ends_with(persona_path)
-    })
-}
-
-// Cape physics helper functions - Modified to patch existing files
-fn is_mobs_json_file(c_path: &Path) -> bool {
-    if !is_cape_physics_enabled() {
-        return false;
-    }
-    
-    let path_str = c_path.to_string_lossy();
-    
-    // Check for mobs.json in models folder
-    let mobs_patterns = [
-        "models/mobs.json",
-        "/models/mobs.json",
-        "vanilla/models/mobs.json",
-        "resource_packs/vanilla/models/mobs.json",
-        "assets/resource_packs/vanilla/models/mobs.json",
-    ];
-    
-    mobs_patterns.iter().any(|pattern| {
-        path_str.contains(pattern) || path_str.ends_with(pattern)
-    })
 }
 
-fn is_player_animation_file(c_path: &Path) -> bool {
-    if !is_cape_physics_enabled() {
-        return false;
-    }
-    
-    let path_str = c_path.to_string_lossy();
-    
-    // Check for player.animation.json in animations folder
-    let animation_patterns = [
-        "animations/player.animation.json",
-        "/animations/player.animation.json",
-        "vanilla/animations/player.animation.json",
-        "resource_packs/vanilla/animations/player.animation.json",
-        "assets/resource_packs/vanilla/animations/player.animation.json",
-    ];
-    
-    animation_patterns.iter().any(|pattern| {
-        path_str.contains(pattern) || path_str.ends_with(pattern)
-    })
+// `aasset` may be closed already, it is only checked for null
+unsafe fn trace_open(
+    path: &str,
+    mode: libc::c_int,
+    aasset: *mut AAsset,
+    rule: Option<&AssetRule>,
+    result: *mut AAsset,
+    patched_apk: bool,
+    started: Instant,
+) {
+    let fake = FakeAsset::from_handle(result);
+    let served = match (rule.map(|rule| &rule.action), fake) {
+        (Some(Action::Block), None) if result.is_null() => Served::Blocked,
+        (_, None) if result.is_null() => Served::Missing,
+        (_, Some(_)) if patched_apk => Served::Patched,
+        (Some(Action::Replace(_)), Some(_)) => Served::Embedded,
+        (Some(Action::Patch(_)), Some(_)) => Served::Patched,
+        (Some(Action::Override(Source::Folder)), Some(_)) => Served::Override,
+        (Some(Action::Override(Source::Archive)), Some(_)) => Served::Archive,
+        (Some(Action::Redirect(_)), Some(_)) => Served::ResourcePack,
+        // Rules that fell back to the apk file
+        _ => Served::Vanilla,
+    };
+    let size = match fake {
+        Some(fake) => Some(fake.length() as u64),
+        None if result.is_null() => None,
+        None => u64::try_from(ndk_sys::AAsset_getLength64(result)).ok(),
+    };
+    tracer::record(&TraceEvent {
+        path,
+        mode: tracer::mode_name(mode),
+        existed: !aasset.is_null(),
+        served,
+        rule: rule.filter(|_| served != Served::Vanilla).map(|rule| rule.name),
+        size,
+        micros: tracer::micros_since(started),
+    });
 }
 
-fn patch_mobs_json(original_data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    // Parse the original mobs.json
-    let mut json_value: Value = serde_json::from_slice(original_data)?;
-    
-    // Parse our custom cape geometry
-    let cape_geometry: Value = serde_json::from_str(CUSTOM_CAPE_GEOMETRY_JSON)?;
-    
-    // Extract the cape geometry object from our custom data
-    if let Some(geometries) = cape_geometry["minecraft:geometry"].as_array() {
-        if let Some(cape_geo) = geometries.iter().find(|geo| {
-            geo["description"]["identifier"].as_str() == Some("geometry.cape")
-        }) {
-            // Find and replace geometry.cape in the original mobs.json
-            if let Some(original_geometries) = json_value["minecraft:geometry"].as_array_mut() {
-                // Remove existing cape geometry if it exists
-                original_geometries.retain(|geo| {
-                    geo["description"]["identifier"].as_str() != Some("geometry.cape")
-                });
-                
-                // Add our custom cape geometry
-                original_geometries.push(cape_geo.clone());
-                
-                log::info!("Successfully patched geometry.cape in mobs.json");
-            } else {
-                // If minecraft:geometry doesn't exist, create it
-                json_value["minecraft:geometry"] = serde_json::json!([cape_geo.clone()]);
-                log::info!("Created minecraft:geometry array and added geometry.cape in mobs.json");
-            }
-        }
+// Swaps the real handle for one of ours serving `data`, files that
+// are not in the apk get a handle too so features can add new ones
+unsafe fn serve(
+    aasset: *mut AAsset,
+    asset_path: &AssetPath,
+    rule: &AssetRule,
+    data: AssetData,
+) -> *mut AAsset {
+    if dump::wants(asset_path) {
+        dump_intercepted(aasset, asset_path, rule, Some(data.as_ref()));
+    }
+    if aasset.is_null() {
+        log::info!("Injecting a file that is not in the apk");
+    } else {
+        ndk_sys::AAsset_close(aasset);
     }
-    
-    // Serialize back to JSON
-    let patched_data = serde_json::to_vec_pretty(&json_value)?;
-    Ok(patched_data)
+    FakeAsset::into_handle(data)
 }
 
-fn patch_player_animation_json(original_data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    // Parse the original player.animation.json
-    let mut json_value: Value = serde_json::from_slice(original_data)?;
-    
-    // Parse our custom cape animation
-    let cape_animation: Value = serde_json::from_str(CUSTOM_CAPE_ANIMATION_JSON)?;
-    
-    // Extract the cape animation object from our custom data
-    if let Some(cape_anim) = cape_animation["animations"]["animation.player.cape"].as_object() {
-        // Ensure animations object exists
-        if json_value["animations"].is_null() {
-            json_value["animations"] = serde_json::json!({});
-        }
-        
-        // Replace or add the animation.player.cape
-        if let Some(animations) = json_value["animations"].as_object_mut() {
-            animations.insert("animation.player.cape".to_string(), Value::Object(cape_anim.clone()));
-            log::info!("Successfully patched animation.player.cape in player.animation.json");
-        }
+// Hands back the real asset rewound, after a guard or patch read it
+unsafe fn rewound(aasset: *mut AAsset) -> *mut AAsset {
+    if !aasset.is_null() {
+        ndk_sys::AAsset_seek64(aasset, 0, libc::SEEK_SET);
     }
-    
-    // Serialize back to JSON
-    let patched_data = serde_json::to_vec_pretty(&json_value)?;
-    Ok(patched_data)
+    aasset
 }
 
-pub(crate) unsafe fn open(
-    man: *mut AAssetManager,
-    fname: *const libc::c_char,
-    mode: libc::c_int,
-) -> *mut ndk_sys::AAsset {
-    let aasset = unsafe { ndk_sys::AAssetManager_open(man, fname, mode) };
-    let c_str = unsafe { CStr::from_ptr(fname) };
-    let raw_cstr = c_str.to_bytes();
-    let os_str = OsStr::from_bytes(raw_cstr);
-    let c_path: &Path = Path::new(os_str);
-    
-    let Some(os_filename) = c_path.file_name() else {
-        log::warn!("Path had no filename: {c_path:?}");
-        return aasset;
-    };
-
-    // Debug logging for features
-    if is_cape_physics_enabled() {
-        let path_str = c_path.to_string_lossy();
-        if path_str.contains("cape") || path_str.contains("animation") || path_str.contains("entity") || path_str.contains("mobs.json") || path_str.contains("player.animation.json") {
-            log::info!("Cape physics enabled - checking file: {}", c_path.display());
-        }
-    }
-    
-    if is_particles_disabler_enabled() {
-        let path_str = c_path.to_string_lossy();
-        if path_str.contains("particle") || path_str.contains("effect") {
-            log::info!("Particles disabler enabled - checking file: {}", c_path.display());
-        }
+// Whole contents of a real asset from the start
+unsafe fn read_original(aasset: *mut AAsset) -> Option<Vec<u8>> {
+    if aasset.is_null() || ndk_sys::AAsset_seek64(aasset, 0, libc::SEEK_SET) != 0 {
+        return None;
     }
-
-    // Block persona files if classic skins enabled
-    if is_persona_file_to_block(c_path) {
-        log::info!("Blocking persona file due to classic_skins enabled: {}", c_path.display());
-        if !aasset.is_null() {
-            ndk_sys::AAsset_close(aasset);
+    let length = usize::try_from(ndk_sys::AAsset_getLength64(aasset)).ok()?;
+    let mut data = vec![0u8; length];
+    let mut filled = 0;
+    while filled < length {
+        let read = ndk_sys::AAsset_read(aasset, data[filled..].as_mut_ptr().cast(), length - filled);
+        if read <= 0 {
+            return None;
         }
-        return std::ptr::null_mut();
+        filled += read as usize;
     }
+    Some(data)
+}
 
-    // Block entire particles folder if particles disabler enabled
-    if is_particles_folder_to_block(c_path) {
-        log::info!("Blocking particles file due to particles_disabler enabled: {}", c_path.display());
-        if !aasset.is_null() {
-            ndk_sys::AAsset_close(aasset);
+unsafe fn dump_intercepted(
+    aasset: *mut AAsset,
+    asset_path: &AssetPath,
+    rule: &AssetRule,
+    served: Option<&[u8]>,
+) {
+    let original = read_original(aasset);
+    dump::dump(asset_path, rule.name, original.as_deref(), served);
+}
+
+// Asks the ResourcePackManager for a file, converting materials if needed
+fn load_from_packs(man: *mut AAssetManager, mapping: &PackMapping, file: &str) -> Option<Vec<u8>> {
+    cxx::let_cxx_string!(cxx_out = "");
+    let loadfn = match crate::RPM_LOAD.get() {
+        Some(ptr) => ptr,
+        None => {
+            log::warn!("ResourcePackManager fn is not ready yet?");
+            return None;
         }
-        return std::ptr::null_mut();
-    }
-    
-    // Custom splashes
-    if os_filename == "splashes.json" {
-        log::info!("Intercepting splashes.json with custom content");
-        let buffer = CUSTOM_SPLASHES_JSON.as_bytes().to_vec();
-        let mut wanted_lock = WANTED_ASSETS.lock().unwrap();
-        wanted_lock.insert(AAssetPtr(aasset), Cursor::new(buffer));
-        return aasset;
-    }
-    
-    // Custom loading messages
-    if os_filename == "loading_messages.json" {
-        log::info!("Intercepting loading_messages.json with custom content");
-        let buffer = CUSTOM_LOADING_MESSAGES_JSON.as_bytes().to_vec();
-        let mut wanted_lock = WANTED_ASSETS.lock().unwrap();
-        wanted_lock.insert(AAssetPtr(aasset), Cursor::new(buffer));
-        return aasset;
+    };
+    let mut arraybuf = [0; 128];
+    let file_path = opt_path_join(&mut arraybuf, &[Path::new(&mapping.pack), Path::new(file)]);
+    let packm_ptr = crate::PACKM_OBJ.load(std::sync::atomic::Ordering::Acquire);
+    let resource_loc = ResourceLocation::from_str(file_path.as_ref());
+    log::info!("loading rpck file: {:#?}", &file_path);
+    if packm_ptr.is_null() {
+        log::error!("ResourcePackManager ptr is null");
+        return None;
     }
-    
-    // Cape physics patching - Patch existing files instead of creating new ones
-    if is_mobs_json_file(c_path) && !aasset.is_null() {
-        log::info!("Cape physics: Patching mobs.json with custom cape geometry: {}", c_path.display());
-        
-        // Read the original file
-        let original_length = ndk_sys::AAsset_getLength(aasset) as usize;
-        let mut original_data = vec![0u8; original_length];
-        let read_result = ndk_sys::AAsset_read(aasset, original_data.as_mut_ptr() as *mut libc::c_void, original_length);
-        
-        if read_result == original_length as i32 {
-            match patch_mobs_json(&original_data) {
-                Ok(patched_data) => {
-                    let mut wanted_lock = WANTED_ASSETS.lock().unwrap();
-                    wanted_lock.insert(AAssetPtr(aasset), Cursor::new(patched_data));
-                    return aasset;
-                }
-                Err(e) => {
-                    log::error!("Failed to patch mobs.json: {}", e);
-                    // Return original asset on error
-                }
-            }
-        } else {
-            log::error!("Failed to read original mobs.json data");
-        }
-        
-        // Reset asset position on error
-        ndk_sys::AAsset_seek(aasset, 0, libc::SEEK_SET);
-        return aasset;
+    unsafe { loadfn(packm_ptr, resource_loc, cxx_out.as_mut()) };
+    if cxx_out.is_empty() {
+        log::info!("File was not found");
+        return None;
     }
-    
-    if is_player_animation_file(c_path) && !aasset.is_null() {
-        log::info!("Cape physics: Patching player.animation.json with custom cape animation: {}", c_path.display());
-        
-        // Read the original file
-        let original_length = ndk_sys::AAsset_getLength(aasset) as usize;
-        let mut original_data = vec![0u8; original_length];
-        let read_result = ndk_sys::AAsset_read(aasset, original_data.as_mut_ptr() as *mut libc::c_void, original_length);
-        
-        if read_result == original_length as i32 {
-            match patch_player_animation_json(&original_data) {
-                Ok(patched_data) => {
-                    let mut wanted_lock = WANTED_ASSETS.lock().unwrap();
-                    wanted_lock.insert(AAssetPtr(aasset), Cursor::new(patched_data));
-                    return aasset;
-                }
-                Err(e) => {
-                    log::error!("Failed to patch player.animation.json: {}", e);
-                    // Return original asset on error
-                }
-            }
-        } else {
-            log::error!("Failed to read original player.animation.json data");
+    let buffer = if mapping.convert_materials && file.ends_with(".material.bin") {
+        match process_material(man, file, cxx_out.as_bytes()) {
+            Some(updated) => updated,
+            None => cxx_out.as_bytes().to_vec(),
         }
-        
-        // Reset asset position on error
-        ndk_sys::AAsset_seek(aasset, 0, libc::SEEK_SET);
-        return aasset;
-    }
-
-    // Java clouds texture replacement
-    if is_clouds_texture_file(c_path) {
-        log::info!("Intercepting clouds texture with Java clouds texture: {}", c_path.display());
-        let buffer = JAVA_CLOUDS_TEXTURE.to_vec();
-        let mut wanted_lock = WANTED_ASSETS.lock().unwrap();
-        wanted_lock.insert(AAssetPtr(aasset), Cursor::new(buffer));
-        return aasset;
-    }
-
-    // Classic skins replacements
-    if is_classic_skins_steve_texture_file(c_path) {
-        log::info!("Intercepting steve.png with classic Steve texture: {}", c_path.display());
-        let buffer = CLASSIC_STEVE_TEXTURE.to_vec();
-        let mut wanted_lock = WANTED_ASSETS.lock().unwrap();
-        wanted_lock.insert(AAssetPtr(aasset), Cursor::new(buffer));
-        return aasset;
-    }
-    
-    if is_classic_skins_alex_texture_file(c_path) {
-        log::info!("Intercepting alex.png with classic Alex texture: {}", c_path.display());
-        let buffer = CLASSIC_ALEX_TEXTURE.to_vec();
-        let mut wanted_lock = WANTED_ASSETS.lock().unwrap();
-        wanted_lock.insert(AAssetPtr(aasset), Cursor::new(buffer));
-        return aasset;
-    }
-    
-    if is_classic_skins_json_file(c_path) {
-        log::info!("Intercepting skins.json with classic skins content: {}", c_path.display());
-        let buffer = CUSTOM_SKINS_JSON.as_bytes().to_vec();
-        let mut wanted_lock = WANTED_ASSETS.lock().unwrap();
-        wanted_lock.insert(AAssetPtr(aasset), Cursor::new(buffer));
-        return aasset;
-    }
-    
-    // No hurt cam camera replacements
-    if is_no_hurt_cam_enabled() {
-        let path_str = c_path.to_string_lossy();
-        
-        if path_str.contains("cameras/") {
-            if os_filename == "first_person.json" {
-                log::info!("Intercepting cameras/first_person.json with custom content (nohurtcam enabled)");
-                let buffer = CUSTOM_FIRST_PERSON_JSON.as_bytes().to_vec();
-                let mut wanted_lock = WANTED_ASSETS.lock().unwrap();
-                wanted_lock.insert(AAssetPtr(aasset), Cursor::new(buffer));
-                return aasset;
-            }
-            
-            if os_filename == "third_person.json" {
-                log::info!("Intercepting cameras/third_person.json with custom content (nohurtcam enabled)");
-                let buffer = CUSTOM_THIRD_PERSON_JSON.as_bytes().to_vec();
-                let mut wanted_lock = WANTED_ASSETS.lock().unwrap();
-                wanted_lock.insert(AAssetPtr(aasset), Cursor::new(buffer));
-                return aasset;
-            }
-            
-            if os_filename == "third_person_front.json" {
-                log::info!("Intercepting cameras/third_person_front.json with custom content (nohurtcam enabled)");
-                let buffer = CUSTOM_THIRD_PERSON_FRONT_JSON.as_bytes().to_vec();
-                let mut wanted_lock = WANTED_ASSETS.lock().unwrap();
-                wanted_lock.insert(AAssetPtr(aasset), Cursor::new(buffer));
-                return aasset;
-            }
-        }
-    }
-
-    // Material replacements
-    let filename_str = os_filename.to_string_lossy();
-    if let Some(no_fog_data) = get_no_fog_material_data(&filename_str) {
-        log::info!("Intercepting {} with no-fog material (no-fog enabled)", filename_str);
-        let buffer = no_fog_data.to_vec();
-        let mut wanted_lock = WANTED_ASSETS.lock().unwrap();
-        wanted_lock.insert(AAssetPtr(aasset), Cursor::new(buffer));
-        return aasset;
-    }
-    
-    if let Some(java_cubemap_data) = get_java_cubemap_material_data(&filename_str) {
-        log::info!("Intercepting {} with java-cubemap material (java-cubemap enabled)", filename_str);
-        let buffer = java_cubemap_data.to_vec();
-        let mut wanted_lock = WANTED_ASSETS.lock().unwrap();
-        wanted_lock.insert(AAssetPtr(aasset), Cursor::new(buffer));
-        return aasset;
-    }
-
-    // Resource pack loading logic
-    let stripped = match c_path.strip_prefix("assets/") {
-        Ok(yay) => yay,
-        Err(_e) => c_path,
-    };
-    
-    let replacement_list = folder_list! {
-        apk: "gui/dist/hbui/" -> pack: "hbui/",
-        apk: "skin_packs/persona/" -> pack: "persona/",
-        apk: "renderer/" -> pack: "renderer/",
-        apk: "resource_packs/vanilla/cameras/" -> pack: "vanilla_cameras/",
+    } else {
+        cxx_out.as_bytes().to_vec()
     };
-    
-    for replacement in replacement_list {
-        if let Ok(file) = stripped.strip_prefix(replacement.0) {
-            cxx::let_cxx_string!(cxx_out = "");
-            let loadfn = match crate::RPM_LOAD.get() {
-                Some(ptr) => ptr,
-                None => {
-                    log::warn!("ResourcePackManager fn is not ready yet?");
-                    return aasset;
-                }
-            };
-            let mut arraybuf = [0; 128];
-            let file_path = opt_path_join(&mut arraybuf, &[Path::new(replacement.1), file]);
-            let packm_ptr = crate::PACKM_OBJ.load(std::sync::atomic::Ordering::Acquire);
-            let resource_loc = ResourceLocation::from_str(file_path.as_ref());
-            log::info!("loading rpck file: {:#?}", &file_path);
-            if packm_ptr.is_null() {
-                log::error!("ResourcePackManager ptr is null");
-                return aasset;
-            }
-            loadfn(packm_ptr, resource_loc, cxx_out.as_mut());
-            if cxx_out.is_empty() {
-                log::info!("File was not found");
-                return aasset;
-            }
-            let buffer = if os_filename.as_encoded_bytes().ends_with(b".material.bin") {
-                match process_material(man, cxx_out.as_bytes()) {
-                    Some(updated) => updated,
-                    None => cxx_out.as_bytes().to_vec(),
-                }
-            } else {
-                cxx_out.as_bytes().to_vec()
-            };
-            let mut wanted_lock = WANTED_ASSETS.lock().unwrap();
-            wanted_lock.insert(AAssetPtr(aasset), Cursor::new(buffer));
-            return aasset;
-        }
-    }
-    return aasset;
+    Some(buffer)
 }
 
 fn opt_path_join<'a>(bytes: &'a mut [u8; 128], paths: &[&Path]) -> Cow<'a, CStr> {
@@ -623,28 +420,66 @@ fn opt_path_join<'a>(bytes: &'a mut [u8; 128], paths: &[&Path]) -> Cow<'a, CStr>
     Cow::Borrowed(guh)
 }
 
-fn process_material(man: *mut AAssetManager, data: &[u8]) -> Option<Vec<u8>> {
-    let mcver = MC_VERSION.get_or_init(|| {
-        let pointer = match std::ptr::NonNull::new(man) {
-            Some(yay) => yay,
-            None => {
-                log::warn!("AssetManager is null?, preposterous, mc detection failed");
-                return None;
-            }
-        };
-        let manager = unsafe { ndk::asset::AssetManager::from_ptr(pointer) };
-        get_current_mcver(manager)
-    });
-    let mcver = (*mcver)?;
+fn process_material(man: *mut AAssetManager, name: &str, data: &[u8]) -> Option<Vec<u8>> {
+    let mcver = game_version::detect(man)?.material;
+    let config = get_config();
+    if config.debug_materials {
+        material_inspect::log_material(name, data);
+    }
+    let strip = config.strip_foreign_shaders;
+    let options = material_options(name, strip);
+    let input = sha256(data);
+    match material_cache::load(&input, mcver, &options) {
+        Some(Cached::Converted(output)) => return Some(output),
+        Some(Cached::Unchanged) => return None,
+        None => {}
+    }
+    let output = convert_material(name, data, mcver, strip);
+    material_cache::store(&input, mcver, &options, output.as_deref());
+    output
+}
+
+// Suffix for material cache entries made with these options
+fn material_options(name: &str, strip: bool) -> String {
+    let mut options = String::new();
+    if strip {
+        options.push_str("_essl");
+    }
+    let patches = [shader_patch::fingerprint(), material_patch::fingerprint()];
+    let mut patched = false;
+    for fingerprint in patches.into_iter().flatten() {
+        options.push('_');
+        options.push_str(fingerprint);
+        patched = true;
+    }
+    // Patches are picked by file name, identical bytes under another name
+    // can come out different
+    if patched {
+        let file_name = name.rsplit('/').next().unwrap_or(name);
+        options.push('_');
+        options.push_str(&sha256_hex(file_name.as_bytes())[..8]);
+    }
+    options
+}
+
+fn convert_material(
+    name: &str,
+    data: &[u8],
+    mcver: MinecraftVersion,
+    strip: bool,
+) -> Option<Vec<u8>> {
     for version in materialbin::ALL_VERSIONS {
-        let material: CompiledMaterialDefinition = match data.pread_with(0, version) {
+        let mut material: CompiledMaterialDefinition = match data.pread_with(0, version) {
             Ok(data) => data,
             Err(e) => {
                 log::trace!("[version] Parsing failed: {e}");
                 continue;
             }
         };
-        if version == mcver {
+        let stripped = strip && shader_strip::strip_foreign(name, &mut material);
+        let patched = shader_patch::patch_material(name, &mut material);
+        let redefined = material_patch::patch_material(name, &mut material);
+        if version == mcver && !stripped && !patched && !redefined {
             return None;
         }
         let mut output = Vec::with_capacity(data.len());
@@ -659,21 +494,19 @@ fn process_material(man: *mut AAssetManager, data: &[u8]) -> Option<Vec<u8>> {
 }
 
 pub(crate) unsafe fn seek64(aasset: *mut AAsset, off: off64_t, whence: libc::c_int) -> off64_t {
-    let mut wanted_assets = WANTED_ASSETS.lock().unwrap();
-    let file = match wanted_assets.get_mut(&AAssetPtr(aasset)) {
-        Some(file) => file,
-        None => return ndk_sys::AAsset_seek64(aasset, off, whence),
-    };
-    seek_facade(off, whence, file) as off64_t
+    match FakeAsset::from_handle(aasset) {
+        Some(file) => file.seek(off, whence) as off64_t,
+        None => ndk_sys::AAsset_seek64(aasset, off, whence),
+    }
 }
 
+// off_t is only 32 bits on armv7, elsewhere the conversion does nothing
+#[allow(clippy::useless_conversion)]
 pub(crate) unsafe fn seek(aasset: *mut AAsset, off: off_t, whence: libc::c_int) -> off_t {
-    let mut wanted_assets = WANTED_ASSETS.lock().unwrap();
-    let file = match wanted_assets.get_mut(&AAssetPtr(aasset)) {
-        Some(file) => file,
-        None => return ndk_sys::AAsset_seek(aasset, off, whence),
-    };
-    seek_facade(off.into(), whence, file) as off_t
+    match FakeAsset::from_handle(aasset) {
+        Some(file) => file.seek(off.into(), whence) as off_t,
+        None => ndk_sys::AAsset_seek(aasset, off, whence),
+    }
 }
 
 pub(crate) unsafe fn read(
@@ -681,10 +514,8 @@ pub(crate) unsafe fn read(
     buf: *mut libc::c_void,
     count: libc::size_t,
 ) -> libc::c_int {
-    let mut wanted_assets = WANTED_ASSETS.lock().unwrap();
-    let file = match wanted_assets.get_mut(&AAssetPtr(aasset)) {
-        Some(file) => file,
-        None => return ndk_sys::AAsset_read(aasset, buf, count),
+    let Some(file) = FakeAsset::from_handle(aasset) else {
+        return ndk_sys::AAsset_read(aasset, buf, count);
     };
     let rs_buffer = core::slice::from_raw_parts_mut(buf as *mut u8, count);
     let read_total = match file.read(rs_buffer) {
@@ -698,125 +529,123 @@ pub(crate) unsafe fn read(
 }
 
 pub(crate) unsafe fn len(aasset: *mut AAsset) -> off_t {
-    let wanted_assets = WANTED_ASSETS.lock().unwrap();
-    let file = match wanted_assets.get(&AAssetPtr(aasset)) {
-        Some(file) => file,
-        None => return ndk_sys::AAsset_getLength(aasset),
-    };
-    file.get_ref().len() as off_t
+    match FakeAsset::from_handle(aasset) {
+        Some(file) => file.length() as off_t,
+        None => ndk_sys::AAsset_getLength(aasset),
+    }
 }
 
 pub(crate) unsafe fn len64(aasset: *mut AAsset) -> off64_t {
-    let wanted_assets = WANTED_ASSETS.lock().unwrap();
-    let file = match wanted_assets.get(&AAssetPtr(aasset)) {
-        Some(file) => file,
-        None => return ndk_sys::AAsset_getLength64(aasset),
-    };
-    file.get_ref().len() as off64_t
+    match FakeAsset::from_handle(aasset) {
+        Some(file) => file.length() as off64_t,
+        None => ndk_sys::AAsset_getLength64(aasset),
+    }
 }
 
 pub(crate) unsafe fn rem(aasset: *mut AAsset) -> off_t {
-    let wanted_assets = WANTED_ASSETS.lock().unwrap();
-    let file = match wanted_assets.get(&AAssetPtr(aasset)) {
-        Some(file) => file,
-        None => return ndk_sys::AAsset_getRemainingLength(aasset),
-    };
-    (file.get_ref().len() - file.position() as usize) as off_t
+    match FakeAsset::from_handle(aasset) {
+        Some(file) => file.remaining() as off_t,
+        None => ndk_sys::AAsset_getRemainingLength(aasset),
+    }
 }
 
 pub(crate) unsafe fn rem64(aasset: *mut AAsset) -> off64_t {
-    let wanted_assets = WANTED_ASSETS.lock().unwrap();
-    let file = match wanted_assets.get(&AAssetPtr(aasset)) {
-        Some(file) => file,
-        None => return ndk_sys::AAsset_getRemainingLength64(aasset),
-    };
-    (file.get_ref().len() - file.position() as usize) as off64_t
+    match FakeAsset::from_handle(aasset) {
+        Some(file) => file.remaining() as off64_t,
+        None => ndk_sys::AAsset_getRemainingLength64(aasset),
+    }
 }
 
 pub(crate) unsafe fn close(aasset: *mut AAsset) {
-    let mut wanted_assets = WANTED_ASSETS.lock().unwrap();
-    if wanted_assets.remove(&AAssetPtr(aasset)).is_none() {
+    if !FakeAsset::close(aasset) {
         ndk_sys::AAsset_close(aasset);
     }
 }
 
 pub(crate) unsafe fn get_buffer(aasset: *mut AAsset) -> *const libc::c_void {
-    let mut wanted_assets = WANTED_ASSETS.lock().unwrap();
-    let file = match wanted_assets.get_mut(&AAssetPtr(aasset)) {
-        Some(file) => file,
-        None => return ndk_sys::AAsset_getBuffer(aasset),
-    };
-    file.get_mut().as_mut_ptr().cast()
+    match FakeAsset::from_handle(aasset) {
+        Some(file) => file.buffer().cast(),
+        None => ndk_sys::AAsset_getBuffer(aasset),
+    }
 }
 
-pub(crate) unsafe fn fd_dummy(
+pub(crate) unsafe fn open_fd(
     aasset: *mut AAsset,
     out_start: *mut off_t,
     out_len: *mut off_t,
 ) -> libc::c_int {
-    let wanted_assets = WANTED_ASSETS.lock().unwrap();
-    match wanted_assets.get(&AAssetPtr(aasset)) {
-        Some(_) => {
-            log::error!("WE GOT BUSTED NOOO");
+    let Some(file) = FakeAsset::from_handle(aasset) else {
+        return ndk_sys::AAsset_openFileDescriptor(aasset, out_start, out_len);
+    };
+    match file.open_fd() {
+        Ok((fd, len)) => {
+            *out_start = 0;
+            *out_len = len as off_t;
+            fd.into_raw_fd()
+        }
+        Err(e) => {
+            log::error!("Cannot make an fd for fake asset: {e}");
             -1
         }
-        None => ndk_sys::AAsset_openFileDescriptor(aasset, out_start, out_len),
     }
 }
 
-pub(crate) unsafe fn fd_dummy64(
+pub(crate) unsafe fn open_fd64(
     aasset: *mut AAsset,
     out_start: *mut off64_t,
     out_len: *mut off64_t,
 ) -> libc::c_int {
-    let wanted_assets = WANTED_ASSETS.lock().unwrap();
-    match wanted_assets.get(&AAssetPtr(aasset)) {
-        Some(_) => {
-            log::error!("WE GOT BUSTED NOOO");
+    let Some(file) = FakeAsset::from_handle(aasset) else {
+        return ndk_sys::AAsset_openFileDescriptor64(aasset, out_start, out_len);
+    };
+    match file.open_fd() {
+        Ok((fd, len)) => {
+            *out_start = 0;
+            *out_len = len as off64_t;
+            fd.into_raw_fd()
+        }
+        Err(e) => {
+            log::error!("Cannot make an fd for fake asset: {e}");
             -1
         }
-        None => ndk_sys::AAsset_openFileDescriptor64(aasset, out_start, out_len),
     }
 }
 
 pub(crate) unsafe fn is_alloc(aasset: *mut AAsset) -> libc::c_int {
-    let wanted_assets = WANTED_ASSETS.lock().unwrap();
-    match wanted_assets.get(&AAssetPtr(aasset)) {
+    match FakeAsset::from_handle(aasset) {
         Some(_) => false as libc::c_int,
         None => ndk_sys::AAsset_isAllocated(aasset),
     }
 }
 
-fn seek_facade(offset: i64, whence: libc::c_int, file: &mut Cursor<Vec<u8>>) -> i64 {
-    let offset = match whence {
-        libc::SEEK_SET => {
-            let u64_off = match u64::try_from(offset) {
-                Ok(uoff) => uoff,
-                Err(e) => {
-                    log::error!("signed ({offset}) to unsigned failed: {e}");
-                    return -1;
-                }
-            };
-            io::SeekFrom::Start(u64_off)
-        }
-        libc::SEEK_CUR => io::SeekFrom::Current(offset),
-        libc::SEEK_END => io::SeekFrom::End(offset),
-        _ => {
-            log::error!("Invalid seek whence");
-            return -1;
-        }
-    };
-    match file.seek(offset) {
-        Ok(new_offset) => match new_offset.try_into() {
-            Ok(int) => int,
-            Err(err) => {
-                log::error!("u64 ({new_offset}) to i64 failed: {err}");
-                -1
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embedded::{JAVA_CUBEMAP_MATERIALS, NO_FOG_MATERIALS};
+
+    // Embedded materials are stored in one version and have to come out
+    // readable in whichever one the game uses
+    #[test]
+    fn embedded_materials_round_trip() {
+        for embedded in NO_FOG_MATERIALS.iter().chain(&JAVA_CUBEMAP_MATERIALS) {
+            let data = embedded.get().expect("embedded material inflates");
+            let original = materialbin::ALL_VERSIONS
+                .into_iter()
+                .find_map(|version| data.pread_with::<CompiledMaterialDefinition>(0, version).ok())
+                .unwrap_or_else(|| panic!("{} parses in no version", embedded.path));
+            for version in materialbin::ALL_VERSIONS {
+                let converted = convert_material(embedded.path, data, version, false);
+                let converted = converted.as_deref().unwrap_or(data);
+                let material: CompiledMaterialDefinition = converted
+                    .pread_with(0, version)
+                    .unwrap_or_else(|e| panic!("{} as {version}: {e}", embedded.path));
+                assert_eq!(material.name, original.name, "{} as {version}", embedded.path);
+                assert!(
+                    material.passes.keys().eq(original.passes.keys()),
+                    "{} as {version} lost passes",
+                    embedded.path
+                );
             }
-        },
-        Err(err) => {
-            log::error!("aasset seek failed: {err}");
-            -1
         }
     }
 }
@@ -0,0 +1,156 @@
+//! Override archives (`.zip` and `.mcpack`) in `origin_mods/packs/`.
+//!
+//! Only the central directories are read at startup, entries get
+//! decompressed when the game actually opens them.
+//! A `.zip` mirrors apk paths like the overrides folder, a `.mcpack` is
+//! a resource pack and is laid over `resource_packs/vanilla/`.
+use crate::asset_path::{child_name, AssetPath};
+use crate::config::CONFIG_DIR;
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+use zip::ZipArchive;
+
+struct Archive {
+    path: PathBuf,
+    zip: Mutex<ZipArchive<File>>,
+}
+
+#[derive(Default)]
+struct ArchiveIndex {
+    archives: Vec<Archive>,
+    // Canonical path -> (archive, entry index)
+    entries: HashMap<String, (usize, usize)>,
+}
+
+static ARCHIVES: OnceLock<ArchiveIndex> = OnceLock::new();
+
+pub fn packs_dir() -> PathBuf {
+    Path::new(CONFIG_DIR).join("packs")
+}
+
+pub fn init_archives() {
+    let dir = packs_dir();
+    if let Err(e) = fs::create_dir_all(&dir) {
+        log::warn!("Failed to create packs directory: {e}");
+    }
+    let index = match build_index(&dir) {
+        Ok(index) => index,
+        Err(e) => {
+            log::warn!("Failed to index override archives: {e}");
+            ArchiveIndex::default()
+        }
+    };
+    log::info!(
+        "Indexed {} files from {} archives",
+        index.entries.len(),
+        index.archives.len()
+    );
+    let _ = ARCHIVES.set(index);
+}
+
+// Archives are sorted by file name and later ones win,
+// so `10_tweaks.zip` overrides `00_base.zip`
+fn build_index(dir: &Path) -> io::Result<ArchiveIndex> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_archive = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("zip") || ext.eq_ignore_ascii_case("mcpack"));
+        if is_archive && path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut index = ArchiveIndex::default();
+    for path in paths {
+        let zip = match File::open(&path).map_err(zip::result::ZipError::from).and_then(ZipArchive::new) {
+            Ok(zip) => zip,
+            Err(e) => {
+                log::warn!("Skipping broken archive {}: {e}", path.display());
+                continue;
+            }
+        };
+        let is_mcpack = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("mcpack"));
+        let archive_id = index.archives.len();
+        let root = if is_mcpack { mcpack_root(&zip) } else { String::new() };
+        for entry_id in 0..zip.len() {
+            let Some(name) = zip.name_for_index(entry_id) else {
+                continue;
+            };
+            if name.ends_with('/') {
+                continue;
+            }
+            let Some(relative) = name.strip_prefix(root.as_str()) else {
+                continue;
+            };
+            let apk_path = if is_mcpack {
+                format!("resource_packs/vanilla/{relative}")
+            } else {
+                relative.to_owned()
+            };
+            let Some(canonical) = AssetPath::new(&apk_path) else {
+                log::warn!("Skipping archive entry outside the apk: {name}");
+                continue;
+            };
+            index.entries.insert(canonical.as_str().to_owned(), (archive_id, entry_id));
+        }
+        log::info!("Loaded override archive {}", path.display());
+        index.archives.push(Archive {
+            path,
+            zip: Mutex::new(zip),
+        });
+    }
+    Ok(index)
+}
+
+// Packs are often zipped with their folder, find where manifest.json is
+fn mcpack_root(zip: &ZipArchive<File>) -> String {
+    zip.file_names()
+        .filter_map(|name| name.strip_suffix("manifest.json"))
+        .filter(|root| root.is_empty() || root.ends_with('/'))
+        .min_by_key(|root| root.len())
+        .unwrap_or_default()
+        .to_owned()
+}
+
+pub fn has_archive_entry(path: &AssetPath) -> bool {
+    ARCHIVES
+        .get()
+        .is_some_and(|index| index.entries.contains_key(path.as_str()))
+}
+
+pub fn read_archive_entry(path: &AssetPath) -> io::Result<Vec<u8>> {
+    let index = ARCHIVES
+        .get()
+        .ok_or_else(|| io::Error::other("archives are not indexed"))?;
+    let &(archive_id, entry_id) = index
+        .entries
+        .get(path.as_str())
+        .ok_or(io::ErrorKind::NotFound)?;
+    let archive = &index.archives[archive_id];
+    let mut zip = archive.zip.lock().unwrap_or_else(|e| e.into_inner());
+    let mut entry = zip.by_index(entry_id)?;
+    log::info!("Reading {} from {}", entry.name(), archive.path.display());
+    let mut buffer = Vec::with_capacity(entry.size() as usize);
+    entry.read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
+
+/// Names of archive entries directly inside a canonical folder
+pub fn archive_names_in(dir: &str) -> Vec<&'static str> {
+    let Some(index) = ARCHIVES.get() else {
+        return Vec::new();
+    };
+    index
+        .entries
+        .keys()
+        .filter_map(|path| child_name(dir, path))
+        .collect()
+}
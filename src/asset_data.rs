@@ -0,0 +1,39 @@
+//! Backing storage for the fake assets we hand to the game.
+use std::sync::Arc;
+
+/// Bytes of a replaced asset, embedded data and shared cache entries
+/// are served as is instead of being copied for every open
+#[derive(Clone)]
+pub enum AssetData {
+    Static(&'static [u8]),
+    Shared(Arc<[u8]>),
+    Owned(Vec<u8>),
+}
+
+impl AsRef<[u8]> for AssetData {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            AssetData::Static(data) => data,
+            AssetData::Shared(data) => data,
+            AssetData::Owned(data) => data,
+        }
+    }
+}
+
+impl From<&'static [u8]> for AssetData {
+    fn from(data: &'static [u8]) -> Self {
+        AssetData::Static(data)
+    }
+}
+
+impl From<Arc<[u8]>> for AssetData {
+    fn from(data: Arc<[u8]>) -> Self {
+        AssetData::Shared(data)
+    }
+}
+
+impl From<Vec<u8>> for AssetData {
+    fn from(data: Vec<u8>) -> Self {
+        AssetData::Owned(data)
+    }
+}
@@ -0,0 +1,156 @@
+//! Directory listings that match what `AAssetManager_open` serves.
+//!
+//! The game lists some folders before opening files from them, so files that
+//! only exist in overrides, archives or embedded rules have to show up here
+//! and blocked files have to disappear. Like [`crate::fake_asset`], listings we
+//! changed are handed out as our own handles starting with a magic word.
+use crate::archives::archive_names_in;
+use crate::asset_path::AssetPath;
+use crate::config::get_config;
+use crate::overrides::override_names_in;
+use crate::rules::{self, Action};
+use ndk_sys::{AAssetDir, AAssetManager};
+use std::{
+    collections::BTreeSet,
+    ffi::{CStr, CString},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+// Different from the asset magic so a dir is never taken for an asset
+#[cfg(target_pointer_width = "64")]
+const MAGIC: usize = 0x004F_5249_4744_4952;
+#[cfg(target_pointer_width = "32")]
+const MAGIC: usize = 0xFFFF_F0F3;
+
+#[repr(C)]
+struct FakeDir {
+    // Must stay the first field
+    magic: usize,
+    names: Vec<CString>,
+    next: AtomicUsize,
+}
+
+impl FakeDir {
+    unsafe fn from_handle<'a>(dir: *const AAssetDir) -> Option<&'a FakeDir> {
+        if dir.is_null() || dir.cast::<usize>().read() != MAGIC {
+            return None;
+        }
+        Some(&*dir.cast::<FakeDir>())
+    }
+}
+
+pub(crate) unsafe fn open_dir(
+    man: *mut AAssetManager,
+    dir_name: *const libc::c_char,
+) -> *mut AAssetDir {
+    let dir = ndk_sys::AAssetManager_openDir(man, dir_name);
+    let Ok(raw_dir) = CStr::from_ptr(dir_name).to_str() else {
+        return dir;
+    };
+    let Some(dir_path) = AssetPath::new(raw_dir.trim_end_matches('/')) else {
+        return dir;
+    };
+    let dir_path = dir_path.as_str();
+
+    let mut real = Vec::new();
+    if !dir.is_null() {
+        loop {
+            let name = ndk_sys::AAssetDir_getNextFileName(dir);
+            if name.is_null() {
+                break;
+            }
+            real.push(CStr::from_ptr(name).to_owned());
+        }
+    }
+    let Some(names) = merge_listing(dir_path, &real) else {
+        if !dir.is_null() {
+            ndk_sys::AAssetDir_rewind(dir);
+        }
+        return dir;
+    };
+    if !dir.is_null() {
+        ndk_sys::AAssetDir_close(dir);
+    }
+    log::info!("Serving modified listing of '{dir_path}' with {} files", names.len());
+    let fake = Box::new(FakeDir {
+        magic: MAGIC,
+        names,
+        next: AtomicUsize::new(0),
+    });
+    Box::into_raw(fake).cast()
+}
+
+// None when the listing would come out the same as the apk one
+fn merge_listing(dir: &str, real: &[CString]) -> Option<Vec<CString>> {
+    let config = get_config();
+    let is_blocked = |name: &str| {
+        let path = join(dir, name);
+        AssetPath::new(&path).is_some_and(|path| {
+            rules::find_rule(&path, config, true).is_some_and(|rule| matches!(rule.action, Action::Block))
+        })
+    };
+    let mut changed = false;
+    let mut names = BTreeSet::new();
+    for name in real {
+        // Non utf8 names can't match any rule, keep them as they are
+        if name.to_str().is_ok_and(&is_blocked) {
+            changed = true;
+            continue;
+        }
+        names.insert(name.clone());
+    }
+    let injected = override_names_in(dir)
+        .into_iter()
+        .chain(archive_names_in(dir))
+        .chain(rules::injected_names_in(dir, config));
+    for name in injected {
+        if is_blocked(name) {
+            continue;
+        }
+        let Ok(name) = CString::new(name) else {
+            continue;
+        };
+        changed |= names.insert(name);
+    }
+    changed.then(|| names.into_iter().collect())
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
+pub(crate) unsafe fn next_file_name(dir: *mut AAssetDir) -> *const libc::c_char {
+    let Some(fake) = FakeDir::from_handle(dir) else {
+        return ndk_sys::AAssetDir_getNextFileName(dir);
+    };
+    let index = fake.next.fetch_add(1, Ordering::Relaxed);
+    match fake.names.get(index) {
+        Some(name) => name.as_ptr(),
+        None => {
+            // Don't let the counter run away on repeated calls at the end
+            fake.next.store(fake.names.len(), Ordering::Relaxed);
+            std::ptr::null()
+        }
+    }
+}
+
+pub(crate) unsafe fn rewind(dir: *mut AAssetDir) {
+    match FakeDir::from_handle(dir) {
+        Some(fake) => fake.next.store(0, Ordering::Relaxed),
+        None => ndk_sys::AAssetDir_rewind(dir),
+    }
+}
+
+pub(crate) unsafe fn close_dir(dir: *mut AAssetDir) {
+    if FakeDir::from_handle(dir).is_none() {
+        ndk_sys::AAssetDir_close(dir);
+        return;
+    }
+    let mut fake = Box::from_raw(dir.cast::<FakeDir>());
+    // Volatile so the store is not dropped along with the box
+    std::ptr::write_volatile(&mut fake.magic, 0);
+}
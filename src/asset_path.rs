@@ -0,0 +1,273 @@
+//! Canonical form of the paths the game hands to AAssetManager.
+//!
+//! The same file can be asked for as `assets/resource_packs/vanilla/x`,
+//! `resource_packs/vanilla/x` or `resource_packs//vanilla/./x`, this
+//! turns all of them into `resource_packs/vanilla/x` and splits off the pack
+//! so rules can match inside every pack of a kind. `..` is resolved, a path
+//! that climbs above the apk root has no canonical form.
+use std::borrow::Cow;
+
+/// Kind of pack an asset lives in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackRoot {
+    /// `resource_packs/<name>/`
+    ResourcePack,
+    /// `skin_packs/<name>/`
+    SkinPack,
+    /// `gui/dist/hbui/`
+    Hbui,
+    /// Not inside any pack, like `renderer/materials/`
+    Apk,
+}
+
+#[derive(Debug, Clone)]
+pub struct AssetPath<'a> {
+    full: Cow<'a, str>,
+    root: PackRoot,
+    // Where the pack relative part starts in `full`
+    split: usize,
+}
+
+impl<'a> AssetPath<'a> {
+    pub fn new(raw: &'a str) -> Option<Self> {
+        let full = normalize(raw)?;
+        let (root, split) = split_root(&full);
+        Some(Self { full, root, split })
+    }
+
+    /// Whole canonical path, `resource_packs/vanilla/splashes.json`
+    pub fn as_str(&self) -> &str {
+        &self.full
+    }
+
+    pub fn root(&self) -> PackRoot {
+        self.root
+    }
+
+    /// Path inside the pack, `splashes.json`
+    pub fn relative(&self) -> &str {
+        &self.full[self.split..]
+    }
+
+    pub fn file_name(&self) -> &str {
+        self.full.rsplit('/').next().unwrap_or(&self.full)
+    }
+}
+
+fn normalize(raw: &str) -> Option<Cow<'_, str>> {
+    let needs_cleanup = raw.contains('\\')
+        || raw.contains("//")
+        || raw.starts_with("./")
+        || raw.contains("/./")
+        || raw.starts_with('/')
+        || raw.split('/').any(|part| part == "..");
+    let path = if needs_cleanup {
+        let replaced = raw.replace('\\', "/");
+        let mut parts: Vec<&str> = Vec::new();
+        for part in replaced.split('/') {
+            match part {
+                "" | "." => {}
+                // Popping past the start would leave the apk
+                ".." => {
+                    parts.pop()?;
+                }
+                part => parts.push(part),
+            }
+        }
+        Cow::Owned(parts.join("/"))
+    } else {
+        Cow::Borrowed(raw)
+    };
+    Some(match path {
+        Cow::Borrowed(path) => Cow::Borrowed(path.strip_prefix("assets/").unwrap_or(path)),
+        Cow::Owned(path) => match path.strip_prefix("assets/") {
+            Some(stripped) => Cow::Owned(stripped.to_owned()),
+            None => Cow::Owned(path),
+        },
+    })
+}
+
+fn split_root(path: &str) -> (PackRoot, usize) {
+    const HBUI: &str = "gui/dist/hbui/";
+    if path.starts_with(HBUI) {
+        return (PackRoot::Hbui, HBUI.len());
+    }
+    for (folder, root) in [
+        ("resource_packs/", PackRoot::ResourcePack),
+        ("skin_packs/", PackRoot::SkinPack),
+    ] {
+        let Some(rest) = path.strip_prefix(folder) else {
+            continue;
+        };
+        // Pack name is the next component, a bare `resource_packs/x` is not in a pack
+        if let Some(name_len) = rest.find('/') {
+            return (root, folder.len() + name_len + 1);
+        }
+    }
+    (PackRoot::Apk, 0)
+}
+
+/// File name of `path` if it sits directly in the canonical folder `dir`
+pub fn child_name<'a>(dir: &str, path: &'a str) -> Option<&'a str> {
+    let name = if dir.is_empty() {
+        path
+    } else {
+        path.strip_prefix(dir)?.strip_prefix('/')?
+    };
+    (!name.is_empty() && !name.contains('/')).then_some(name)
+}
+
+/// Matches a canonical path against a glob.
+/// `*` and `?` stay inside one component, `**` spans any number of them.
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('/').collect();
+    let path: Vec<&str> = path.split('/').collect();
+    match_components(&pattern, &path)
+}
+
+fn match_components(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| match_components(rest, &path[skip..])),
+        Some((first, rest)) => match path.split_first() {
+            Some((component, path_rest)) => {
+                match_component(first.as_bytes(), component.as_bytes())
+                    && match_components(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+fn match_component(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|skip| match_component(rest, &text[skip..])),
+        Some((b'?', rest)) => !text.is_empty() && match_component(rest, &text[1..]),
+        Some((byte, rest)) => text.first() == Some(byte) && match_component(rest, &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Paths as the game asks for them, the canonical path, its root and
+    // the part inside the pack
+    const CORPUS: [(&str, &str, PackRoot, &str); 10] = [
+        (
+            "assets/resource_packs/vanilla/splashes.json",
+            "resource_packs/vanilla/splashes.json",
+            PackRoot::ResourcePack,
+            "splashes.json",
+        ),
+        (
+            "resource_packs/vanilla_1.20.50/textures/particle/particles.png",
+            "resource_packs/vanilla_1.20.50/textures/particle/particles.png",
+            PackRoot::ResourcePack,
+            "textures/particle/particles.png",
+        ),
+        (
+            "assets/resource_packs//vanilla/./cameras/first_person.json",
+            "resource_packs/vanilla/cameras/first_person.json",
+            PackRoot::ResourcePack,
+            "cameras/first_person.json",
+        ),
+        (
+            "/assets/resource_packs/chemistry/models/entity/balloon.geo.json",
+            "resource_packs/chemistry/models/entity/balloon.geo.json",
+            PackRoot::ResourcePack,
+            "models/entity/balloon.geo.json",
+        ),
+        (
+            "assets/skin_packs/persona/08_Kai_Dcast.json",
+            "skin_packs/persona/08_Kai_Dcast.json",
+            PackRoot::SkinPack,
+            "08_Kai_Dcast.json",
+        ),
+        (
+            "skin_packs\\vanilla\\skins.json",
+            "skin_packs/vanilla/skins.json",
+            PackRoot::SkinPack,
+            "skins.json",
+        ),
+        (
+            "assets/gui/dist/hbui/index.html",
+            "gui/dist/hbui/index.html",
+            PackRoot::Hbui,
+            "index.html",
+        ),
+        (
+            "assets/renderer/materials/RenderChunk.material.bin",
+            "renderer/materials/RenderChunk.material.bin",
+            PackRoot::Apk,
+            "renderer/materials/RenderChunk.material.bin",
+        ),
+        (
+            "./assets/renderer/materials/../materials/Sky.material.bin",
+            "renderer/materials/Sky.material.bin",
+            PackRoot::Apk,
+            "renderer/materials/Sky.material.bin",
+        ),
+        (
+            "resource_packs/vanilla",
+            "resource_packs/vanilla",
+            PackRoot::Apk,
+            "resource_packs/vanilla",
+        ),
+    ];
+
+    #[test]
+    fn corpus_is_canonical() {
+        for (raw, full, root, relative) in CORPUS {
+            let path = AssetPath::new(raw).unwrap();
+            assert_eq!(path.as_str(), full, "{raw}");
+            assert_eq!(path.root(), root, "{raw}");
+            assert_eq!(path.relative(), relative, "{raw}");
+            assert!(full.ends_with(path.file_name()), "{raw}");
+            // Canonical paths stay as they are
+            assert_eq!(AssetPath::new(full).unwrap().as_str(), full);
+        }
+    }
+
+    #[test]
+    fn climbing_out_is_rejected() {
+        for raw in ["..", "../lib/arm64/libminecraftpe.so", "assets/../../x", "resource_packs/../../x"] {
+            assert!(AssetPath::new(raw).is_none(), "{raw}");
+        }
+        assert_eq!(AssetPath::new("assets/../x").unwrap().as_str(), "x");
+    }
+
+    #[test]
+    fn globs() {
+        let matches = [
+            ("resource_packs/*/particles/**", "resource_packs/vanilla/particles/smoke.json"),
+            ("resource_packs/*/particles/**", "resource_packs/vanilla/particles"),
+            ("resource_packs/vanilla/**/loading_messages.json", "resource_packs/vanilla/loading_messages.json"),
+            ("resource_packs/vanilla/**/loading_messages.json", "resource_packs/vanilla/texts/a/loading_messages.json"),
+            ("skin_packs/persona/**/08_Kai_Dcast.json", "skin_packs/persona/08_Kai_Dcast.json"),
+            ("renderer/materials/*.material.bin", "renderer/materials/UIText.material.bin"),
+            ("textures/particle?/**", "textures/particles/a.png"),
+        ];
+        for (pattern, path) in matches {
+            assert!(glob_match(pattern, path), "{pattern} should match {path}");
+        }
+        let misses = [
+            ("resource_packs/*/particles/**", "resource_packs/particles/smoke.json"),
+            ("renderer/materials/*.material.bin", "renderer/materials/sub/UIText.material.bin"),
+            ("renderer/*", "renderer"),
+            ("textures/particle?/**", "textures/particle/a.png"),
+        ];
+        for (pattern, path) in misses {
+            assert!(!glob_match(pattern, path), "{pattern} should not match {path}");
+        }
+    }
+
+    #[test]
+    fn children() {
+        assert_eq!(child_name("resource_packs/vanilla", "resource_packs/vanilla/splashes.json"), Some("splashes.json"));
+        assert_eq!(child_name("resource_packs/vanilla", "resource_packs/vanilla/texts/en_US.lang"), None);
+        assert_eq!(child_name("resource_packs/vanill", "resource_packs/vanilla/splashes.json"), None);
+        assert_eq!(child_name("", "manifest.json"), Some("manifest.json"));
+    }
+}
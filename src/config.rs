@@ -1,13 +1,17 @@
 use std::{
+    collections::HashMap,
     fs::{self, File},
     io::{Read, Write},
     path::Path,
     sync::OnceLock,
 };
 use serde::{Deserialize, Serialize};
+use crate::guard::GuardConfig;
+use crate::rules::{default_pack_mappings, with_default_mappings, PackMapping};
 
 // Config structure
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct ModConfig {
     #[serde(rename = "Nohurtcam")]
     pub no_hurt_cam: bool,
@@ -32,6 +36,55 @@ pub struct ModConfig {
     
     #[serde(rename = "cape_physics")]
     pub cape_physics: bool,
+
+    // Keep the indentation of patched json instead of pretty printing it
+    #[serde(rename = "json_keep_format")]
+    pub json_keep_format: bool,
+
+    // Write every asset open to origin_mods/trace/
+    #[serde(rename = "trace_assets")]
+    pub trace_assets: bool,
+
+    #[serde(rename = "trace_flush_secs")]
+    pub trace_flush_secs: u64,
+
+    // Write original and served bytes of matching assets to origin_mods/dump/
+    #[serde(rename = "dump_assets")]
+    pub dump_assets: bool,
+
+    #[serde(rename = "dump_globs")]
+    pub dump_globs: Vec<String>,
+
+    // Pinned hashes and policies for replacements, by rule name
+    #[serde(rename = "guards")]
+    pub guards: HashMap<String, GuardConfig>,
+
+    // Memory for patch results of files that get opened again, 0 turns it off
+    #[serde(rename = "patch_cache_mb")]
+    pub patch_cache_mb: usize,
+
+    // Keep converted resource pack materials in origin_mods/material_cache/
+    #[serde(rename = "material_cache")]
+    pub material_cache: bool,
+
+    // Log a json summary of every material that gets processed
+    #[serde(rename = "debug_materials")]
+    pub debug_materials: bool,
+
+    // Drop non ESSL shaders from materials before they reach the game. All
+    // ESSL versions stay, only the game knows which one the device runs
+    #[serde(rename = "strip_foreign_shaders")]
+    pub strip_foreign_shaders: bool,
+
+    // Material format to use instead of detecting it, as named in the log
+    #[serde(rename = "material_version")]
+    pub material_version: Option<String>,
+
+    // Apk folders resource packs can provide files for, in order. Entries
+    // replace the default with the same name or are added after the defaults
+    #[serde(rename = "pack_mappings")]
+    pub pack_mappings: Vec<PackMapping>,
+
     // You can add more fields as needed
     // #[serde(rename = "CustomField")]
     // pub custom_field: bool,
@@ -48,6 +101,18 @@ impl Default for ModConfig {
             classic_skins: false,
             threed_skin_layer: false,
             cape_physics: false,
+            json_keep_format: true,
+            trace_assets: false,
+            trace_flush_secs: 10,
+            dump_assets: false,
+            dump_globs: vec!["**".to_owned()],
+            guards: HashMap::new(),
+            patch_cache_mb: 32,
+            material_cache: true,
+            debug_materials: false,
+            strip_foreign_shaders: false,
+            material_version: None,
+            pack_mappings: default_pack_mappings(),
             // custom_field: false,
         }
     }
@@ -57,14 +122,22 @@ impl Default for ModConfig {
 static CONFIG: OnceLock<ModConfig> = OnceLock::new();
 
 // Config file path
-const CONFIG_DIR: &str = "/storage/emulated/0/Android/data/com.origin.launcher/files/origin_mods";
+pub const CONFIG_DIR: &str = "/storage/emulated/0/Android/data/com.origin.launcher/files/origin_mods";
 const CONFIG_FILE: &str = "/storage/emulated/0/Android/data/com.origin.launcher/files/origin_mods/config.json";
 
 pub fn init_config() {
-    let config = load_or_create_config();
+    let mut config = load_or_create_config();
+    config.pack_mappings = with_default_mappings(std::mem::take(&mut config.pack_mappings));
+    config.pack_mappings.retain_mut(PackMapping::normalize);
     CONFIG.set(config).expect("Failed to set config");
 }
 
+// Tests never read the config file, rules are built from the defaults
+#[cfg(test)]
+pub fn init_test_config() {
+    CONFIG.get_or_init(ModConfig::default);
+}
+
 pub fn get_config() -> &'static ModConfig {
     CONFIG.get().expect("Config not initialized")
 }
@@ -116,40 +189,3 @@ fn save_config(config: &ModConfig) -> Result<(), Box<dyn std::error::Error>> {
     file.sync_all()?;
     Ok(())
 }
-
-// Helper functions to check individual settings
-pub fn is_no_hurt_cam_enabled() -> bool {
-    get_config().no_hurt_cam
-}
-
-pub fn is_no_fog_enabled() -> bool {
-    get_config().no_fog
-}
-
-pub fn is_particles_disabler_enabled() -> bool {
-    get_config().particles_disabler
-}
-
-pub fn is_java_clouds_enabled() -> bool {
-    get_config().java_clouds
-}
-
-pub fn is_java_cubemap_enabled() -> bool {
-    get_config().java_cubemap
-}
-
-pub fn is_classic_skins_enabled() -> bool {
-    get_config().classic_skins
-}
-
-pub fn is_threed_skin_layer_enabled() -> bool {
-    get_config().threed_skin_layer
-}
-
-pub fn is_cape_physics_enabled() -> bool {
-    get_config().cape_physics
-}
-// You can add more helper functions for other config values
-// pub fn is_custom_field_enabled() -> bool {
-//     get_config().custom_field
-// }
\ No newline at end of file
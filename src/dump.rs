@@ -0,0 +1,127 @@
+//! Dump mode, writes what the game asked for and what it actually got.
+//!
+//! With `dump_assets` on, every intercepted asset matching one of `dump_globs`
+//! is written to `origin_mods/dump/original/<path>` and `dump/served/<path>`,
+//! and a line with both hashes is added to `dump/manifest.jsonl`.
+use crate::asset_path::{glob_match, AssetPath};
+use crate::config::{ModConfig, CONFIG_DIR};
+use crate::hashing::sha256_hex;
+use serde::Serialize;
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+struct Dumper {
+    dir: PathBuf,
+    globs: Vec<String>,
+    // Opens can happen on several threads at once
+    manifest: Mutex<File>,
+}
+
+static DUMPER: OnceLock<Dumper> = OnceLock::new();
+
+#[derive(Serialize)]
+struct ManifestEntry<'a> {
+    path: &'a str,
+    rule: &'a str,
+    original: Option<FileInfo>,
+    served: Option<FileInfo>,
+}
+
+#[derive(Serialize)]
+struct FileInfo {
+    size: usize,
+    sha256: String,
+}
+
+impl FileInfo {
+    fn of(data: &[u8]) -> Self {
+        Self {
+            size: data.len(),
+            sha256: sha256_hex(data),
+        }
+    }
+}
+
+pub fn init_dump(config: &ModConfig) {
+    if !config.dump_assets {
+        return;
+    }
+    let dir = Path::new(CONFIG_DIR).join("dump");
+    let manifest = fs::create_dir_all(&dir).and_then(|_| File::create(dir.join("manifest.jsonl")));
+    let manifest = match manifest {
+        Ok(file) => file,
+        Err(e) => {
+            log::warn!("Failed to set up dump directory, dumping is off: {e}");
+            return;
+        }
+    };
+    log::info!("Dumping assets matching {:?} to {}", config.dump_globs, dir.display());
+    let _ = DUMPER.set(Dumper {
+        dir,
+        globs: config.dump_globs.clone(),
+        manifest: Mutex::new(manifest),
+    });
+}
+
+/// Whether `path` should be dumped, false right away when dumping is off
+#[inline]
+pub fn wants(path: &AssetPath) -> bool {
+    DUMPER.get().is_some_and(|dumper| {
+        dumper
+            .globs
+            .iter()
+            .any(|glob| glob_match(glob, path.as_str()))
+    })
+}
+
+/// Records one intercepted asset, `None` means there was no such file
+/// in the apk or nothing was served
+pub fn dump(path: &AssetPath, rule: &str, original: Option<&[u8]>, served: Option<&[u8]>) {
+    let Some(dumper) = DUMPER.get() else {
+        return;
+    };
+    if let Err(e) = dumper.write(path, rule, original, served) {
+        log::warn!("Failed to dump {}: {e}", path.as_str());
+    }
+}
+
+impl Dumper {
+    fn write(
+        &self,
+        path: &AssetPath,
+        rule: &str,
+        original: Option<&[u8]>,
+        served: Option<&[u8]>,
+    ) -> io::Result<()> {
+        // Anything but plain names could put the file outside the dump folder
+        let relative = Path::new(path.as_str());
+        let plain = relative.components().all(|part| matches!(part, Component::Normal(_)));
+        if !plain || relative.as_os_str().is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a plain relative path"));
+        }
+        for (kind, data) in [("original", original), ("served", served)] {
+            let Some(data) = data else {
+                continue;
+            };
+            let file = self.dir.join(kind).join(relative);
+            if let Some(parent) = file.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(file, data)?;
+        }
+        let entry = ManifestEntry {
+            path: path.as_str(),
+            rule,
+            original: original.map(FileInfo::of),
+            served: served.map(FileInfo::of),
+        };
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        let mut manifest = self.manifest.lock().unwrap_or_else(|e| e.into_inner());
+        manifest.write_all(&line)
+    }
+}
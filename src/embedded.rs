@@ -0,0 +1,59 @@
+//! Files shipped inside the library.
+//!
+//! build.rs generates a static for every file in `src/assets/` and a table per
+//! material feature folder. Files are stored deflated when that makes them
+//! smaller and inflated the first time they are used, then kept for the session.
+//! A file that fails to inflate stays unavailable so callers keep the original.
+use flate2::read::DeflateDecoder;
+use std::{io::Read, sync::OnceLock};
+
+pub struct Embedded {
+    /// The apk path for materials, the file name for everything else
+    pub path: &'static str,
+    stored: &'static [u8],
+    size: usize,
+    deflated: bool,
+    data: OnceLock<Option<Box<[u8]>>>,
+}
+
+impl Embedded {
+    const fn new(path: &'static str, stored: &'static [u8], size: usize, deflated: bool) -> Self {
+        Self {
+            path,
+            stored,
+            size,
+            deflated,
+            data: OnceLock::new(),
+        }
+    }
+
+    /// The file contents, None when they fail to inflate
+    pub fn get(&self) -> Option<&[u8]> {
+        if !self.deflated {
+            return Some(self.stored);
+        }
+        self.data
+            .get_or_init(|| {
+                let mut data = Vec::with_capacity(self.size);
+                match DeflateDecoder::new(self.stored).read_to_end(&mut data) {
+                    Ok(_) if data.len() == self.size => Some(data.into_boxed_slice()),
+                    Ok(_) => {
+                        log::error!(
+                            "Embedded {} inflated to {} bytes instead of {}",
+                            self.path,
+                            data.len(),
+                            self.size
+                        );
+                        None
+                    }
+                    Err(e) => {
+                        log::error!("Embedded {} is broken: {e}", self.path);
+                        None
+                    }
+                }
+            })
+            .as_deref()
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/embedded.rs"));
@@ -0,0 +1,240 @@
+//! Our own AAsset handles for replaced assets.
+//!
+//! Instead of keeping the real handle and looking it up in a global map on
+//! every call, the game gets a pointer to a [`FakeAsset`]. Its first word is a
+//! magic value that can never be the `Asset*` a real AAsset starts with, so
+//! telling the two apart is a single load and untouched assets never lock.
+use crate::asset_data::AssetData;
+use crate::memfd;
+use ndk_sys::AAsset;
+use std::{
+    io::{self, Cursor, Read, Seek},
+    os::fd::OwnedFd,
+    sync::{Mutex, MutexGuard, OnceLock},
+};
+
+// Not a valid user space pointer: non-canonical on x86_64 and sets bits
+// below the tag byte on aarch64, on 32 bit it is in kernel space
+#[cfg(target_pointer_width = "64")]
+const MAGIC: usize = 0x004F_5249_4749_4E21;
+#[cfg(target_pointer_width = "32")]
+const MAGIC: usize = 0xFFFF_F0F1;
+
+#[repr(C)]
+pub struct FakeAsset {
+    // Must stay the first field
+    magic: usize,
+    // Handles are used by one thread at a time, this never contends
+    cursor: Mutex<Cursor<AssetData>>,
+    // Created the first time someone wants an fd
+    fd: OnceLock<OwnedFd>,
+}
+
+impl FakeAsset {
+    pub fn into_handle(data: AssetData) -> *mut AAsset {
+        let asset = Box::new(FakeAsset {
+            magic: MAGIC,
+            cursor: Mutex::new(Cursor::new(data)),
+            fd: OnceLock::new(),
+        });
+        Box::into_raw(asset).cast()
+    }
+
+    /// # Safety
+    /// `aasset` has to be null, a live AAsset or a live handle from [`Self::into_handle`]
+    pub unsafe fn from_handle<'a>(aasset: *const AAsset) -> Option<&'a FakeAsset> {
+        if aasset.is_null() {
+            return None;
+        }
+        // Real AAssets start with a pointer so this read is always in bounds
+        let magic = aasset.cast::<usize>().read();
+        if magic != MAGIC {
+            return None;
+        }
+        Some(&*aasset.cast::<FakeAsset>())
+    }
+
+    /// Frees the handle, returns false if it was not ours
+    ///
+    /// # Safety
+    /// Same as [`Self::from_handle`], the handle can't be used afterwards
+    pub unsafe fn close(aasset: *mut AAsset) -> bool {
+        if Self::from_handle(aasset).is_none() {
+            return false;
+        }
+        let mut asset = Box::from_raw(aasset.cast::<FakeAsset>());
+        // Don't let a stale pointer look like ours, a plain store right
+        // before the free could be optimized out
+        std::ptr::write_volatile(&mut asset.magic, 0);
+        true
+    }
+
+    fn cursor(&self) -> MutexGuard<'_, Cursor<AssetData>> {
+        self.cursor.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.cursor().read(buf)
+    }
+
+    pub fn length(&self) -> usize {
+        self.cursor().get_ref().as_ref().len()
+    }
+
+    pub fn remaining(&self) -> usize {
+        let cursor = self.cursor();
+        let len = cursor.get_ref().as_ref().len();
+        len.saturating_sub(cursor.position() as usize)
+    }
+
+    /// Stays valid until the handle is closed
+    pub fn buffer(&self) -> *const u8 {
+        self.cursor().get_ref().as_ref().as_ptr()
+    }
+
+    /// A file descriptor with the asset contents starting at 0 and its length,
+    /// the caller owns the fd
+    pub fn open_fd(&self) -> io::Result<(OwnedFd, usize)> {
+        let cursor = self.cursor();
+        let data = cursor.get_ref().as_ref();
+        let fd = match self.fd.get() {
+            Some(fd) => fd,
+            None => {
+                let fd = memfd::fd_for_bytes(data)?;
+                self.fd.get_or_init(|| fd)
+            }
+        };
+        Ok((memfd::reopen(fd)?, data.len()))
+    }
+
+    pub fn seek(&self, offset: i64, whence: libc::c_int) -> i64 {
+        let offset = match whence {
+            libc::SEEK_SET => {
+                let u64_off = match u64::try_from(offset) {
+                    Ok(uoff) => uoff,
+                    Err(e) => {
+                        log::error!("signed ({offset}) to unsigned failed: {e}");
+                        return -1;
+                    }
+                };
+                io::SeekFrom::Start(u64_off)
+            }
+            libc::SEEK_CUR => io::SeekFrom::Current(offset),
+            libc::SEEK_END => io::SeekFrom::End(offset),
+            _ => {
+                log::error!("Invalid seek whence");
+                return -1;
+            }
+        };
+        match self.cursor().seek(offset) {
+            Ok(new_offset) => match new_offset.try_into() {
+                Ok(int) => int,
+                Err(err) => {
+                    log::error!("u64 ({new_offset}) to i64 failed: {err}");
+                    -1
+                }
+            },
+            Err(err) => {
+                log::error!("aasset seek failed: {err}");
+                -1
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashMap, fs::File, os::fd::AsRawFd, thread, time::Instant};
+
+    // The game maps fds from offset 0 for the given length, whatever the
+    // handle has been read to
+    #[test]
+    fn open_fd_starts_at_zero() {
+        let data: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+        let handle = FakeAsset::into_handle(AssetData::Owned(data.clone()));
+        let asset = unsafe { FakeAsset::from_handle(handle) }.unwrap();
+        asset.read(&mut [0; 100]).unwrap();
+        for _ in 0..2 {
+            let (fd, len) = asset.open_fd().unwrap();
+            assert_eq!(len, data.len());
+            assert_eq!(unsafe { libc::lseek(fd.as_raw_fd(), 0, libc::SEEK_CUR) }, 0);
+            let mut read = Vec::new();
+            File::from(fd).read_to_end(&mut read).unwrap();
+            assert_eq!(read, data);
+        }
+        assert_eq!(asset.remaining(), data.len() - 100);
+        assert!(unsafe { FakeAsset::close(handle) });
+    }
+
+    const THREADS: usize = 8;
+    const READS: usize = 200_000;
+    const CHUNK: usize = 64;
+
+    fn timed(name: &str, work: impl Fn(usize) + Sync) {
+        let started = Instant::now();
+        thread::scope(|scope| {
+            for thread in 0..THREADS {
+                let work = &work;
+                scope.spawn(move || work(thread));
+            }
+        });
+        let elapsed = started.elapsed();
+        let calls = THREADS * READS;
+        println!(
+            "{name}: {elapsed:?} for {calls} calls, {:.1} ns each",
+            elapsed.as_nanos() as f64 / calls as f64
+        );
+    }
+
+    // What handles looked like before FakeAsset, every call locked one map
+    // shared by all assets
+    #[test]
+    #[ignore = "benchmark, run with --ignored --nocapture"]
+    fn bench_read() {
+        let data: &'static [u8] = vec![7; CHUNK * 1024].leak();
+        let handles: Vec<usize> = (0..THREADS)
+            .map(|_| FakeAsset::into_handle(AssetData::Static(data)) as usize)
+            .collect();
+        timed("FakeAsset::read", |thread| {
+            let mut buf = [0; CHUNK];
+            for _ in 0..READS {
+                let asset = unsafe { FakeAsset::from_handle(handles[thread] as *const AAsset) }.unwrap();
+                if asset.read(&mut buf).unwrap() == 0 {
+                    asset.seek(0, libc::SEEK_SET);
+                }
+            }
+        });
+        timed("FakeAsset::from_handle", |thread| {
+            for _ in 0..READS {
+                let handle = std::hint::black_box(handles[thread] as *const AAsset);
+                assert!(unsafe { FakeAsset::from_handle(handle) }.is_some());
+            }
+        });
+        for handle in handles {
+            assert!(unsafe { FakeAsset::close(handle as *mut AAsset) });
+        }
+
+        let map: Mutex<HashMap<usize, Cursor<AssetData>>> = Mutex::new(
+            (0..THREADS)
+                .map(|thread| (thread, Cursor::new(AssetData::Static(data))))
+                .collect(),
+        );
+        timed("Mutex<HashMap> read", |thread| {
+            let mut buf = [0; CHUNK];
+            for _ in 0..READS {
+                let mut map = map.lock().unwrap();
+                let cursor = map.get_mut(&thread).unwrap();
+                if cursor.read(&mut buf).unwrap() == 0 {
+                    cursor.set_position(0);
+                }
+            }
+        });
+        timed("Mutex<HashMap> lookup", |thread| {
+            for _ in 0..READS {
+                let key = std::hint::black_box(thread);
+                assert!(map.lock().unwrap().contains_key(&key));
+            }
+        });
+    }
+}
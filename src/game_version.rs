@@ -0,0 +1,343 @@
+//! Which game the mod runs in, and so which material format it needs.
+//!
+//! The material format is taken from the first of these that works:
+//! - `material_version` in the config
+//! - `origin_mods/game_version.json`, remembered for the GNU build id of
+//!   libminecraftpe.so so a detected version survives restarts, as long as
+//!   a reference material still parses in it
+//! - the game version in the vanilla resource pack manifest, mapped to the
+//!   newest material format not newer than it that a reference material
+//!   actually parses as
+//! - a vote between several apk materials on the versions they parse as
+use crate::config::{get_config, CONFIG_DIR};
+use crate::jsonc;
+use materialbin::{CompiledMaterialDefinition, MinecraftVersion};
+use ndk::asset::AssetManager;
+use ndk_sys::AAssetManager;
+use scroll::Pread;
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::{c_void, CStr, CString},
+    fmt, fs,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+#[derive(Debug, Clone)]
+pub struct GameVersion {
+    /// Like `1.21.50`, None when the vanilla manifest could not be read
+    pub game: Option<String>,
+    /// Format materials have to be in
+    pub material: MinecraftVersion,
+    /// GNU build id of libminecraftpe.so in hex
+    pub build_id: Option<String>,
+}
+
+impl fmt::Display for GameVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (build id {}), materials are {}",
+            self.game.as_deref().unwrap_or("unknown"),
+            self.build_id.as_deref().unwrap_or("unknown"),
+            self.material
+        )
+    }
+}
+
+// What game_version.json holds
+#[derive(Serialize, Deserialize)]
+struct Remembered {
+    build_id: String,
+    material: String,
+}
+
+static GAME_VERSION: OnceLock<Option<GameVersion>> = OnceLock::new();
+
+// Folders assets have been in across game versions
+const ASSET_PREFIXES: [&str; 2] = ["assets/", ""];
+
+// Materials every version ships, several so one odd file can't decide alone
+const REFERENCE_MATERIALS: [&str; 5] = ["UIText", "RenderChunk", "Sky", "Particle", "ActorBanner"];
+
+/// The running game version, detected on the first call
+pub fn detect(man: *mut AAssetManager) -> Option<&'static GameVersion> {
+    GAME_VERSION
+        .get_or_init(|| {
+            let Some(pointer) = std::ptr::NonNull::new(man) else {
+                log::warn!("AssetManager is null?, preposterous, mc detection failed");
+                return None;
+            };
+            let manager = unsafe { AssetManager::from_ptr(pointer) };
+            let version = detect_with(&manager);
+            if version.is_none() {
+                log::error!("Shader fixing is disabled as no mc version was found");
+            }
+            version
+        })
+        .as_ref()
+}
+
+/// The version [`detect`] found, None before it ran or when it failed
+pub fn current() -> Option<&'static GameVersion> {
+    GAME_VERSION.get().and_then(Option::as_ref)
+}
+
+fn detect_with(manager: &AssetManager) -> Option<GameVersion> {
+    let build_id = build_id();
+    let game = manifest_version(manager);
+    let material = configured()
+        .or_else(|| remembered(manager, build_id.as_deref()))
+        .or_else(|| {
+            let found = game
+                .as_deref()
+                .and_then(|game| from_game_version(manager, game))
+                .or_else(|| vote(manager))?;
+            if let Some(build_id) = &build_id {
+                remember(build_id, found);
+            }
+            Some(found)
+        })?;
+    let version = GameVersion {
+        game,
+        material,
+        build_id,
+    };
+    log::info!("Mc version is {version}");
+    Some(version)
+}
+
+fn by_name(name: &str) -> Option<MinecraftVersion> {
+    materialbin::ALL_VERSIONS
+        .into_iter()
+        .find(|version| version.to_string() == name)
+}
+
+fn configured() -> Option<MinecraftVersion> {
+    let name = get_config().material_version.as_deref()?;
+    let version = by_name(name);
+    match version {
+        Some(version) => log::info!("Material version {version} is set in the config"),
+        None => log::warn!("Unknown material_version {name} in the config, detecting it"),
+    }
+    version
+}
+
+fn remembered_file() -> PathBuf {
+    Path::new(CONFIG_DIR).join("game_version.json")
+}
+
+fn remembered(manager: &AssetManager, build_id: Option<&str>) -> Option<MinecraftVersion> {
+    let build_id = build_id?;
+    let data = fs::read(remembered_file()).ok()?;
+    let remembered: Remembered = serde_json::from_slice(&data).ok()?;
+    if remembered.build_id != build_id {
+        log::info!("libminecraftpe.so changed since the last run, detecting the version again");
+        return None;
+    }
+    let version = by_name(&remembered.material)?;
+    // The file could be stale or edited, the apk materials have the last word
+    if !reference_parses(manager, version) {
+        log::warn!("Materials don't parse as the remembered {version}, detecting it again");
+        return None;
+    }
+    Some(version)
+}
+
+fn remember(build_id: &str, material: MinecraftVersion) {
+    let remembered = Remembered {
+        build_id: build_id.to_owned(),
+        material: material.to_string(),
+    };
+    let written = serde_json::to_vec_pretty(&remembered)
+        .map_err(std::io::Error::from)
+        .and_then(|json| fs::write(remembered_file(), json));
+    if let Err(e) = written {
+        log::warn!("Failed to remember the game version: {e}");
+    }
+}
+
+fn read_asset(manager: &AssetManager, path: &str) -> Option<Vec<u8>> {
+    for prefix in ASSET_PREFIXES {
+        let Ok(full) = CString::new(format!("{prefix}{path}")) else {
+            continue;
+        };
+        let Some(mut asset) = manager.open(&full) else {
+            continue;
+        };
+        let mut data = Vec::with_capacity(asset.length());
+        if let Err(e) = asset.read_to_end(&mut data) {
+            log::warn!("Something is wrong with AssetManager, cannot read {path}: {e}");
+            return None;
+        }
+        return Some(data);
+    }
+    None
+}
+
+// Every reference material votes for each version it parses as, ties go to
+// whichever comes first in ALL_VERSIONS
+fn vote(manager: &AssetManager) -> Option<MinecraftVersion> {
+    let mut votes = vec![0; materialbin::ALL_VERSIONS.len()];
+    for material in REFERENCE_MATERIALS {
+        let path = format!("renderer/materials/{material}.material.bin");
+        let Some(data) = read_asset(manager, &path) else {
+            continue;
+        };
+        for (index, version) in materialbin::ALL_VERSIONS.into_iter().enumerate() {
+            if data
+                .pread_with::<CompiledMaterialDefinition>(0, version)
+                .is_ok()
+            {
+                votes[index] += 1;
+            }
+        }
+    }
+    let best = votes.iter().copied().max().filter(|&best| best > 0)?;
+    let winners: Vec<_> = materialbin::ALL_VERSIONS
+        .into_iter()
+        .zip(&votes)
+        .filter(|(_, &count)| count == best)
+        .map(|(version, _)| version)
+        .collect();
+    if winners.len() > 1 {
+        log::warn!(
+            "Materials parse as any of {winners:?}, using {}",
+            winners[0]
+        );
+    }
+    winners.first().copied()
+}
+
+// Newest material format the game version is at least, only trusted when a
+// reference material parses in it so a format change inside a minor
+// version falls through to the vote
+fn from_game_version(manager: &AssetManager, game: &str) -> Option<MinecraftVersion> {
+    let numbers = version_numbers(game);
+    let candidate = materialbin::ALL_VERSIONS
+        .into_iter()
+        .filter(|version| version_numbers(&version.to_string()) <= numbers)
+        .max_by_key(|version| version_numbers(&version.to_string()))?;
+    if !reference_parses(manager, candidate) {
+        log::warn!("Materials don't parse as {candidate}, the format for {game}, voting");
+        return None;
+    }
+    Some(candidate)
+}
+
+fn reference_parses(manager: &AssetManager, version: MinecraftVersion) -> bool {
+    REFERENCE_MATERIALS.iter().any(|material| {
+        read_asset(manager, &format!("renderer/materials/{material}.material.bin")).is_some_and(
+            |data| {
+                data.pread_with::<CompiledMaterialDefinition>(0, version)
+                    .is_ok()
+            },
+        )
+    })
+}
+
+// The numbers in a version name, `1.21.50` and `V1_21_50` both give [1, 21, 50]
+fn version_numbers(name: &str) -> Vec<u32> {
+    name.split(|c: char| !c.is_ascii_digit())
+        .filter_map(|part| part.parse().ok())
+        .collect()
+}
+
+fn manifest_version(manager: &AssetManager) -> Option<String> {
+    let data = read_asset(manager, "resource_packs/vanilla/manifest.json")?;
+    let manifest = jsonc::from_slice(&data).ok()?;
+    let parts = manifest.pointer("/header/version")?.as_array()?;
+    let parts: Option<Vec<String>> = parts
+        .iter()
+        .map(|part| part.as_u64().map(|part| part.to_string()))
+        .collect();
+    Some(parts?.join("."))
+}
+
+// Build id from the notes of the loaded libminecraftpe.so
+fn build_id() -> Option<String> {
+    let mut found: Option<Vec<u8>> = None;
+    unsafe {
+        libc::dl_iterate_phdr(
+            Some(find_build_id),
+            (&mut found as *mut Option<Vec<u8>>).cast(),
+        );
+    }
+    let id = found?;
+    Some(id.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+unsafe extern "C" fn find_build_id(
+    info: *mut libc::dl_phdr_info,
+    _size: usize,
+    data: *mut c_void,
+) -> libc::c_int {
+    let info = &*info;
+    if info.dlpi_name.is_null()
+        || !CStr::from_ptr(info.dlpi_name)
+            .to_bytes()
+            .ends_with(b"libminecraftpe.so")
+    {
+        return 0;
+    }
+    let headers = std::slice::from_raw_parts(info.dlpi_phdr, info.dlpi_phnum as usize);
+    for header in headers
+        .iter()
+        .filter(|header| header.p_type == libc::PT_NOTE)
+    {
+        let start = (info.dlpi_addr as usize + header.p_vaddr as usize) as *const u8;
+        let notes = std::slice::from_raw_parts(start, header.p_memsz as usize);
+        if let Some(id) = gnu_build_id(notes) {
+            *data.cast::<Option<Vec<u8>>>() = Some(id.to_vec());
+            return 1;
+        }
+    }
+    1
+}
+
+fn gnu_build_id(mut notes: &[u8]) -> Option<&[u8]> {
+    const NT_GNU_BUILD_ID: u32 = 3;
+    let align = |len: usize| (len + 3) & !3;
+    while notes.len() >= 12 {
+        let name_size = notes.pread_with::<u32>(0, scroll::LE).ok()? as usize;
+        let desc_size = notes.pread_with::<u32>(4, scroll::LE).ok()? as usize;
+        let kind = notes.pread_with::<u32>(8, scroll::LE).ok()?;
+        let desc_start = 12 + align(name_size);
+        let name = notes.get(12..12 + name_size)?;
+        let desc = notes.get(desc_start..desc_start + desc_size)?;
+        if kind == NT_GNU_BUILD_ID && name == b"GNU\0" {
+            return Some(desc);
+        }
+        notes = notes.get(desc_start + align(desc_size)..)?;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_numbers_compare_like_versions() {
+        assert_eq!(version_numbers("1.21.50"), [1, 21, 50]);
+        assert_eq!(version_numbers("V1_21_20"), [1, 21, 20]);
+        assert!(version_numbers("1.21.5") < version_numbers("1.21.20"));
+        assert!(version_numbers("1.20.80") < version_numbers("1.21"));
+        for version in materialbin::ALL_VERSIONS {
+            assert_eq!(version_numbers(&version.to_string()).len(), 3, "{version}");
+        }
+    }
+
+    #[test]
+    fn finds_the_gnu_build_id() {
+        let mut notes = Vec::new();
+        // An unrelated note first
+        notes.extend_from_slice(&[4, 0, 0, 0, 3, 0, 0, 0, 1, 0, 0, 0]);
+        notes.extend_from_slice(b"Abc\0\x01\x02\x03\0");
+        notes.extend_from_slice(&[4, 0, 0, 0, 5, 0, 0, 0, 3, 0, 0, 0]);
+        notes.extend_from_slice(b"GNU\0\xde\xad\xbe\xef\x01\0\0\0");
+        assert_eq!(gnu_build_id(&notes), Some(&[0xde, 0xad, 0xbe, 0xef, 0x01][..]));
+        assert_eq!(gnu_build_id(&notes[..20]), None);
+    }
+}
@@ -0,0 +1,197 @@
+//! Fingerprint guards for replacements that were made for one game version.
+//!
+//! A guarded rule only applies as is while the apk file it overwrites is in
+//! the same format as the replacement (the json `format_version` or the
+//! material.bin version) and, when pinned in `guards` in the config, has one
+//! of the expected sha256 hashes. On a mismatch the rule policy decides.
+//! Guards and their decisions end up in `origin_mods/status.txt`.
+use crate::asset_path::AssetPath;
+use crate::config::{ModConfig, CONFIG_DIR};
+use crate::hashing::sha256_hex;
+use crate::jsonc;
+use crate::rules::{self, AssetRule};
+use materialbin::CompiledMaterialDefinition;
+use once_cell::sync::Lazy;
+use scroll::Pread;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// What to do when the original is not what a rule was made for
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Policy {
+    /// Serve the apk file untouched
+    Skip,
+    /// Log it and apply the rule anyway
+    Warn,
+    /// Merge a json replacement into the new original instead of replacing it,
+    /// like warn for anything else
+    Merge,
+}
+
+/// Per rule settings from the config, keyed by rule name
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct GuardConfig {
+    /// Accepted sha256 hashes of the original, any of them passes
+    pub sha256: Vec<String>,
+    /// Replaces the built in policy, also guards rules that have none
+    pub policy: Option<Policy>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Apply,
+    Skip,
+    Merge,
+}
+
+// Decisions per canonical path, the original does not change while running
+static DECISIONS: Lazy<Mutex<HashMap<String, Decision>>> = Lazy::new(Default::default);
+
+fn status_file() -> PathBuf {
+    Path::new(CONFIG_DIR).join("status.txt")
+}
+
+fn policy(rule: &AssetRule, config: &ModConfig) -> Option<Policy> {
+    match config.guards.get(rule.name) {
+        Some(pinned) => pinned.policy.or(rule.guard).or(Some(Policy::Warn)),
+        None => rule.guard,
+    }
+}
+
+/// Writes the startup status report listing every guarded rule
+pub fn report_status(config: &ModConfig) {
+    let mut report = String::from("Guarded rules:\n");
+    for rule in rules::all() {
+        let Some(policy) = policy(rule, config) else {
+            continue;
+        };
+        let enabled = if (rule.enabled)(config) { "enabled" } else { "disabled" };
+        let pinned = config
+            .guards
+            .get(rule.name)
+            .map_or(0, |pinned| pinned.sha256.len());
+        report.push_str(&format!(
+            "  {} ({enabled}): policy {policy:?}, {pinned} pinned hashes\n",
+            rule.name
+        ));
+    }
+    report.push_str("Decisions:\n");
+    for line in report.lines() {
+        log::info!("[guard] {line}");
+    }
+    if let Err(e) = fs::write(status_file(), report) {
+        log::warn!("Failed to write status report: {e}");
+    }
+}
+
+/// Checks the original behind `path` for a guarded rule against the bytes
+/// that would replace it, `read_original` only runs until a path is decided
+pub fn decide(
+    rule: &AssetRule,
+    path: &AssetPath,
+    config: &ModConfig,
+    replacement: Option<&[u8]>,
+    read_original: impl FnOnce() -> Option<Vec<u8>>,
+) -> Decision {
+    let Some(policy) = policy(rule, config) else {
+        return Decision::Apply;
+    };
+    let decided = DECISIONS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(path.as_str())
+        .copied();
+    if let Some(decision) = decided {
+        return decision;
+    }
+    // Reading and comparing happens unlocked so other paths are not held up
+    let original = read_original();
+    let problem = match &original {
+        Some(original) => mismatch(rule, config, original, replacement),
+        None => Some("it is not in the apk".to_owned()),
+    };
+    let decision = match (&problem, policy) {
+        (None, _) => Decision::Apply,
+        (Some(_), Policy::Skip) => Decision::Skip,
+        (Some(_), Policy::Merge) if original.is_some() && replacement.is_some_and(is_json) => {
+            Decision::Merge
+        }
+        (Some(_), _) => Decision::Apply,
+    };
+    let mut decisions = DECISIONS.lock().unwrap_or_else(|e| e.into_inner());
+    let decision = match decisions.entry(path.as_str().to_owned()) {
+        // Another open of the same path got here first
+        Entry::Occupied(entry) => return *entry.get(),
+        Entry::Vacant(entry) => *entry.insert(decision),
+    };
+    drop(decisions);
+    if let Some(problem) = problem {
+        let line = format!(
+            "{} on {}: {problem}, {decision:?}",
+            rule.name,
+            path.as_str()
+        );
+        log::warn!("[guard] {line}");
+        append_status(&line);
+    }
+    decision
+}
+
+fn is_json(data: &[u8]) -> bool {
+    format_of(data).starts_with("json")
+}
+
+fn mismatch(
+    rule: &AssetRule,
+    config: &ModConfig,
+    original: &[u8],
+    replacement: Option<&[u8]>,
+) -> Option<String> {
+    if let Some(pinned) = config.guards.get(rule.name).filter(|pinned| !pinned.sha256.is_empty()) {
+        let hash = sha256_hex(original);
+        if !pinned.sha256.iter().any(|known| known.eq_ignore_ascii_case(&hash)) {
+            return Some(format!("unknown sha256 {hash}"));
+        }
+    }
+    let replacement = replacement?;
+    let (ours, theirs) = (format_of(replacement), format_of(original));
+    (ours != theirs).then(|| format!("original is {theirs} but the replacement is {ours}"))
+}
+
+// Something comparable about the format of a file, enough to notice
+// that the game moved on since a replacement was made
+fn format_of(data: &[u8]) -> String {
+    for version in materialbin::ALL_VERSIONS {
+        if data
+            .pread_with::<CompiledMaterialDefinition>(0, version)
+            .is_ok()
+        {
+            return format!("material {version}");
+        }
+    }
+    match jsonc::from_slice(data) {
+        Ok(json) => match json.get("format_version").and_then(|v| v.as_str()) {
+            Some(version) => format!("json {version}"),
+            None => "json without format_version".to_owned(),
+        },
+        Err(_) => "unknown".to_owned(),
+    }
+}
+
+fn append_status(line: &str) {
+    let written = OpenOptions::new()
+        .append(true)
+        .open(status_file())
+        .and_then(|mut file| writeln!(file, "  {line}"));
+    if let Err(e) = written {
+        log::warn!("Failed to update status report: {e}");
+    }
+}
@@ -0,0 +1,21 @@
+//! Content hashes shared by everything that needs to recognise bytes.
+use sha2::{Digest, Sha256};
+
+pub type Hash = [u8; 32];
+
+pub fn sha256(data: &[u8]) -> Hash {
+    Sha256::digest(data).into()
+}
+
+/// Lowercase hex sha256 of `data`
+pub fn sha256_hex(data: &[u8]) -> String {
+    to_hex(&sha256(data))
+}
+
+pub fn to_hex(hash: &Hash) -> String {
+    let mut hex = String::with_capacity(hash.len() * 2);
+    for byte in hash {
+        hex.push_str(&format!("{byte:02x}"));
+    }
+    hex
+}
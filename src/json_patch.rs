@@ -0,0 +1,354 @@
+//! Patches for any json asset the game opens.
+//!
+//! A patch is either an RFC 7396 merge patch (a json object) or an
+//! RFC 6902 JSON Patch (an array of operations). Patches are keyed by the
+//! canonical asset path, embedded ones run first and then the one from
+//! `origin_mods/patches/<canonical path>` if it exists.
+//! Both assets and patch files are parsed as [`jsonc`]. When a replace or
+//! redirect rule serves the file the patches run over what it serves.
+use crate::asset_path::AssetPath;
+use crate::config::{ModConfig, CONFIG_DIR};
+use crate::embedded::{Embedded, CAPE_ANIMATION_PATCH_JSON, CAPE_GEOMETRY_PATCH_JSON};
+use crate::jsonc;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+type PatchError = Box<dyn std::error::Error>;
+
+pub enum JsonPatch {
+    Merge(Value),
+    Operations(Vec<Operation>),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Operation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+    /// Not part of RFC 6902: replaces the element of the array at `path`
+    /// whose `key` pointer equals the one in `value`, or appends it. A missing
+    /// array is created first
+    Upsert { path: String, key: String, value: Value },
+}
+
+impl JsonPatch {
+    pub fn from_value(value: Value) -> Result<Self, PatchError> {
+        match value {
+            Value::Array(_) => Ok(JsonPatch::Operations(serde_json::from_value(value)?)),
+            Value::Object(_) => Ok(JsonPatch::Merge(value)),
+            _ => Err("a patch has to be an object or an array of operations".into()),
+        }
+    }
+
+    pub fn apply(&self, doc: &mut Value) -> Result<(), PatchError> {
+        match self {
+            JsonPatch::Merge(patch) => {
+                merge_patch(doc, patch);
+                Ok(())
+            }
+            JsonPatch::Operations(operations) => {
+                for operation in operations {
+                    apply_operation(doc, operation)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+struct EmbeddedPatch {
+    path: &'static str,
+    enabled: fn(&ModConfig) -> bool,
+    document: &'static Embedded,
+}
+
+static EMBEDDED_PATCHES: [EmbeddedPatch; 2] = [
+    EmbeddedPatch {
+        path: "resource_packs/vanilla/models/mobs.json",
+        enabled: |c| c.cape_physics,
+        document: &CAPE_GEOMETRY_PATCH_JSON,
+    },
+    EmbeddedPatch {
+        path: "resource_packs/vanilla/animations/player.animation.json",
+        enabled: |c| c.cape_physics,
+        document: &CAPE_ANIMATION_PATCH_JSON,
+    },
+];
+
+struct PatchSet {
+    patches: HashMap<String, Vec<JsonPatch>>,
+    keep_format: bool,
+}
+
+static PATCHES: OnceLock<PatchSet> = OnceLock::new();
+
+pub fn patches_dir() -> PathBuf {
+    Path::new(CONFIG_DIR).join("patches")
+}
+
+pub fn init_json_patches(config: &ModConfig) {
+    let mut patches: HashMap<String, Vec<JsonPatch>> = HashMap::new();
+    for embedded in EMBEDDED_PATCHES.iter().filter(|p| (p.enabled)(config)) {
+        let Some(document) = embedded.document.get() else {
+            continue;
+        };
+        let patch = serde_json::from_slice(document)
+            .map_err(PatchError::from)
+            .and_then(JsonPatch::from_value);
+        match patch {
+            Ok(patch) => patches.entry(embedded.path.to_owned()).or_default().push(patch),
+            Err(e) => log::error!("Embedded patch for {} is broken: {e}", embedded.path),
+        }
+    }
+    let dir = patches_dir();
+    if let Err(e) = fs::create_dir_all(&dir) {
+        log::warn!("Failed to create patches directory: {e}");
+    }
+    if let Err(e) = load_patch_dir(&dir, &dir, &mut patches) {
+        log::warn!("Failed to load json patches: {e}");
+    }
+    log::info!("Loaded json patches for {} assets", patches.len());
+    let _ = PATCHES.set(PatchSet {
+        patches,
+        keep_format: config.json_keep_format,
+    });
+}
+
+fn load_patch_dir(
+    root: &Path,
+    dir: &Path,
+    patches: &mut HashMap<String, Vec<JsonPatch>>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            load_patch_dir(root, &path, patches)?;
+            continue;
+        }
+        let Some(relative) = path.strip_prefix(root).ok().and_then(|p| p.to_str()) else {
+            continue;
+        };
+        let patch = fs::read(&path)
+            .map_err(PatchError::from)
+            .and_then(|data| Ok(jsonc::from_slice(&data)?))
+            .and_then(JsonPatch::from_value);
+        match patch {
+            Ok(patch) => {
+                let Some(asset) = AssetPath::new(relative) else {
+                    log::warn!("Skipping json patch outside the apk: {}", path.display());
+                    continue;
+                };
+                log::info!("Loaded json patch for {}", asset.as_str());
+                patches.entry(asset.as_str().to_owned()).or_default().push(patch);
+            }
+            Err(e) => log::warn!("Skipping json patch {}: {e}", path.display()),
+        }
+    }
+    Ok(())
+}
+
+pub fn has_json_patches(path: &AssetPath) -> bool {
+    PATCHES
+        .get()
+        .is_some_and(|set| set.patches.contains_key(path.as_str()))
+}
+
+pub fn apply_json_patches(path: &AssetPath, original_data: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let Some(set) = PATCHES.get() else {
+        return Ok(original_data.to_vec());
+    };
+    let Some(patches) = set.patches.get(path.as_str()) else {
+        return Ok(original_data.to_vec());
+    };
+    let mut doc = jsonc::from_slice(original_data)?;
+    for patch in patches {
+        patch.apply(&mut doc)?;
+    }
+    if set.keep_format {
+        return Ok(jsonc::to_vec_like(&doc, original_data)?);
+    }
+    Ok(serde_json::to_vec_pretty(&doc)?)
+}
+
+/// Lays a whole replacement file over a newer original as a merge patch,
+/// so keys the game added since then survive
+pub fn merge_replacement(original: &[u8], replacement: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut doc = jsonc::from_slice(original)?;
+    merge_patch(&mut doc, &jsonc::from_slice(replacement)?);
+    Ok(jsonc::to_vec_like(&doc, original)?)
+}
+
+/// RFC 7396
+pub fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    let Value::Object(target) = target else {
+        unreachable!();
+    };
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            merge_patch(target.entry(key.as_str()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+fn apply_operation(doc: &mut Value, operation: &Operation) -> Result<(), PatchError> {
+    match operation {
+        Operation::Add { path, value } => add(doc, path, value.clone()),
+        Operation::Remove { path } => remove(doc, path).map(drop),
+        Operation::Replace { path, value } => {
+            let target = doc
+                .pointer_mut(path)
+                .ok_or_else(|| format!("replace: {path} does not exist"))?;
+            *target = value.clone();
+            Ok(())
+        }
+        Operation::Move { from, path } => {
+            let value = remove(doc, from)?;
+            add(doc, path, value)
+        }
+        Operation::Copy { from, path } => {
+            let value = doc
+                .pointer(from)
+                .ok_or_else(|| format!("copy: {from} does not exist"))?
+                .clone();
+            add(doc, path, value)
+        }
+        Operation::Test { path, value } => match doc.pointer(path) {
+            Some(found) if found == value => Ok(()),
+            _ => Err(format!("test: {path} does not match").into()),
+        },
+        Operation::Upsert { path, key, value } => {
+            if doc.pointer(path).is_none() {
+                add(doc, path, Value::Array(Vec::new()))?;
+            }
+            let array = doc
+                .pointer_mut(path)
+                .and_then(Value::as_array_mut)
+                .ok_or_else(|| format!("upsert: {path} is not an array"))?;
+            let wanted = value.pointer(key);
+            match array.iter_mut().find(|item| wanted.is_some() && item.pointer(key) == wanted) {
+                Some(item) => *item = value.clone(),
+                None => array.push(value.clone()),
+            }
+            Ok(())
+        }
+    }
+}
+
+// Splits a json pointer into its parent pointer and the unescaped last token
+fn split_pointer(pointer: &str) -> Result<(&str, String), PatchError> {
+    let Some(index) = pointer.rfind('/') else {
+        return Err(format!("invalid json pointer: {pointer}").into());
+    };
+    let token = pointer[index + 1..].replace("~1", "/").replace("~0", "~");
+    Ok((&pointer[..index], token))
+}
+
+fn add(doc: &mut Value, path: &str, value: Value) -> Result<(), PatchError> {
+    if path.is_empty() {
+        *doc = value;
+        return Ok(());
+    }
+    let (parent, token) = split_pointer(path)?;
+    match doc.pointer_mut(parent) {
+        Some(Value::Object(map)) => {
+            map.insert(token, value);
+            Ok(())
+        }
+        Some(Value::Array(array)) => {
+            let index = if token == "-" {
+                array.len()
+            } else {
+                token.parse::<usize>()?
+            };
+            if index > array.len() {
+                return Err(format!("add: index {index} out of bounds in {parent}").into());
+            }
+            array.insert(index, value);
+            Ok(())
+        }
+        _ => Err(format!("add: parent of {path} does not exist").into()),
+    }
+}
+
+fn remove(doc: &mut Value, path: &str) -> Result<Value, PatchError> {
+    let (parent, token) = split_pointer(path)?;
+    let removed = match doc.pointer_mut(parent) {
+        Some(Value::Object(map)) => map.remove(&token),
+        Some(Value::Array(array)) => match token.parse::<usize>() {
+            Ok(index) if index < array.len() => Some(array.remove(index)),
+            _ => None,
+        },
+        _ => None,
+    };
+    removed.ok_or_else(|| format!("remove: {path} does not exist").into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn upsert(doc: &mut Value, value: Value) -> Result<(), PatchError> {
+        let operation = Operation::Upsert {
+            path: "/minecraft:geometry".to_owned(),
+            key: "/description/identifier".to_owned(),
+            value,
+        };
+        apply_operation(doc, &operation)
+    }
+
+    #[test]
+    fn upsert_creates_a_missing_array() {
+        let mut doc = json!({ "format_version": "1.12.0" });
+        let cape = json!({ "description": { "identifier": "geometry.cape" } });
+        upsert(&mut doc, cape.clone()).unwrap();
+        assert_eq!(doc["minecraft:geometry"], json!([cape]));
+    }
+
+    #[test]
+    fn upsert_replaces_by_key() {
+        let mut doc = json!({ "minecraft:geometry": [
+            { "description": { "identifier": "geometry.humanoid" } },
+            { "description": { "identifier": "geometry.cape" }, "bones": [] },
+        ]});
+        let cape = json!({ "description": { "identifier": "geometry.cape" }, "bones": [{ "name": "root" }] });
+        upsert(&mut doc, cape.clone()).unwrap();
+        let geometry = doc["minecraft:geometry"].as_array().unwrap();
+        assert_eq!(geometry.len(), 2);
+        assert_eq!(geometry[1], cape);
+    }
+
+    #[test]
+    fn upsert_rejects_a_non_array() {
+        let mut doc = json!({ "minecraft:geometry": {} });
+        assert!(upsert(&mut doc, json!({})).is_err());
+    }
+
+    #[test]
+    fn cape_animation_creates_animations() {
+        let document = jsonc::from_slice(CAPE_ANIMATION_PATCH_JSON.get().unwrap()).unwrap();
+        let patch = JsonPatch::from_value(document).unwrap();
+        let mut doc = json!({ "format_version": "1.8.0" });
+        patch.apply(&mut doc).unwrap();
+        assert!(doc["animations"]["animation.player.cape"]["bones"].is_object());
+    }
+}
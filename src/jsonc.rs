@@ -0,0 +1,204 @@
+//! Lenient json as found in vanilla packs.
+//!
+//! Mojang's json has `//` and `/* */` comments, trailing commas and
+//! sometimes a BOM, serde_json rejects all of those so they get
+//! stripped before parsing.
+use serde::Serialize;
+use serde_json::{ser::PrettyFormatter, Serializer, Value};
+use std::borrow::Cow;
+
+pub fn from_slice(data: &[u8]) -> serde_json::Result<Value> {
+    serde_json::from_slice(&strip_jsonc(data))
+}
+
+/// Removes comments, trailing commas and a leading BOM,
+/// borrows when there is nothing to remove
+pub fn strip_jsonc(data: &[u8]) -> Cow<'_, [u8]> {
+    let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+    let without_comments = strip_comments(data);
+    match strip_trailing_commas(&without_comments) {
+        Cow::Borrowed(_) => without_comments,
+        Cow::Owned(stripped) => Cow::Owned(stripped),
+    }
+}
+
+fn strip_comments(data: &[u8]) -> Cow<'_, [u8]> {
+    if !data.contains(&b'/') {
+        return Cow::Borrowed(data);
+    }
+    let mut out = Vec::with_capacity(data.len());
+    let mut in_string = false;
+    let mut i = 0;
+    while i < data.len() {
+        let byte = data[i];
+        if in_string {
+            out.push(byte);
+            if byte == b'\\' {
+                if let Some(&escaped) = data.get(i + 1) {
+                    out.push(escaped);
+                    i += 1;
+                }
+            } else if byte == b'"' {
+                in_string = false;
+            }
+            i += 1;
+            continue;
+        }
+        match (byte, data.get(i + 1)) {
+            (b'"', _) => {
+                in_string = true;
+                out.push(byte);
+                i += 1;
+            }
+            (b'/', Some(b'/')) => {
+                // Keep the newline so line numbers in errors stay right
+                while i < data.len() && data[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            (b'/', Some(b'*')) => {
+                i += 2;
+                while i < data.len() && !(data[i] == b'*' && data.get(i + 1) == Some(&b'/')) {
+                    if data[i] == b'\n' {
+                        out.push(b'\n');
+                    }
+                    i += 1;
+                }
+                i += 2;
+            }
+            _ => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    if out.len() == data.len() {
+        return Cow::Borrowed(data);
+    }
+    Cow::Owned(out)
+}
+
+// Expects comments to be gone already
+fn strip_trailing_commas(data: &[u8]) -> Cow<'_, [u8]> {
+    let mut out: Option<Vec<u8>> = None;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, &byte) in data.iter().enumerate() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if byte == b'\\' {
+                escaped = true;
+            } else if byte == b'"' {
+                in_string = false;
+            }
+        } else if byte == b'"' {
+            in_string = true;
+        } else if byte == b',' {
+            let next = data[i + 1..].iter().find(|b| !b.is_ascii_whitespace());
+            if matches!(next, Some(b'}') | Some(b']')) {
+                out.get_or_insert_with(|| data[..i].to_vec());
+                continue;
+            }
+        }
+        if let Some(out) = out.as_mut() {
+            out.push(byte);
+        }
+    }
+    match out {
+        Some(out) => Cow::Owned(out),
+        None => Cow::Borrowed(data),
+    }
+}
+
+/// Serializes like the original was written: single line stays
+/// single line, otherwise the first indentation found is reused
+pub fn to_vec_like(value: &Value, original: &[u8]) -> serde_json::Result<Vec<u8>> {
+    let Some(indent) = detect_indent(original) else {
+        return serde_json::to_vec(value);
+    };
+    let mut out = Vec::with_capacity(original.len());
+    let mut serializer = Serializer::with_formatter(&mut out, PrettyFormatter::with_indent(indent));
+    value.serialize(&mut serializer)?;
+    Ok(out)
+}
+
+fn detect_indent(data: &[u8]) -> Option<&[u8]> {
+    if !data.contains(&b'\n') {
+        return None;
+    }
+    let indent = data
+        .split(|&b| b == b'\n')
+        .skip(1)
+        .find_map(|line| {
+            let len = line.iter().take_while(|&&b| b == b' ' || b == b'\t').count();
+            (len > 0 && len < line.len()).then(|| &line[..len])
+        })
+        .unwrap_or(b"  ");
+    Some(indent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // Trimmed from vanilla files, comments and trailing commas as shipped
+    const CAMERA: &[u8] = br#"{
+	"format_version": "1.18.10",
+	// Tuned for touch controls
+	"minecraft:camera_entity": {
+		"description": { "identifier": "minecraft:first_person", },
+		/* "shake": { "enabled": true }, */
+		"components": {
+			"minecraft:camera_first_person": {},
+			"minecraft:camera_render_first_person_objects": {},
+		},
+	},
+}
+"#;
+
+    const SPLASHES: &[u8] = b"\xEF\xBB\xBF{\"splashes\": [\"http://minecraft.net\", \"// not a comment\", \"a, ]\",]}";
+
+    #[test]
+    fn parses_vanilla_json() {
+        let camera = from_slice(CAMERA).unwrap();
+        assert_eq!(camera["format_version"], "1.18.10");
+        let entity = &camera["minecraft:camera_entity"];
+        assert_eq!(entity["description"], json!({ "identifier": "minecraft:first_person" }));
+        assert!(entity.get("shake").is_none());
+        assert_eq!(entity["components"].as_object().unwrap().len(), 2);
+
+        let splashes = from_slice(SPLASHES).unwrap();
+        assert_eq!(
+            splashes["splashes"],
+            json!(["http://minecraft.net", "// not a comment", "a, ]"])
+        );
+    }
+
+    #[test]
+    fn plain_json_is_borrowed() {
+        let plain = br#"{"a": [1, 2], "b": "c/d"}"#;
+        assert!(matches!(strip_jsonc(plain), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn keeps_line_numbers() {
+        let broken = b"{\n/* one\ntwo */\n\"a\": nope\n}";
+        let error = from_slice(broken).unwrap_err();
+        assert_eq!(error.line(), 4);
+    }
+
+    #[test]
+    fn writes_like_the_original() {
+        let camera = from_slice(CAMERA).unwrap();
+        let written = to_vec_like(&camera, CAMERA).unwrap();
+        assert!(written.starts_with(b"{\n\t\"format_version\""));
+        assert_eq!(from_slice(&written).unwrap(), camera);
+
+        let splashes = from_slice(SPLASHES).unwrap();
+        let written = to_vec_like(&splashes, SPLASHES).unwrap();
+        assert!(!written.contains(&b'\n'));
+        assert_eq!(serde_json::from_slice::<Value>(&written).unwrap(), splashes);
+    }
+}
@@ -0,0 +1,94 @@
+//! Disk cache for materials converted to the running game version.
+//!
+//! Entries live in `origin_mods/material_cache/<mod version>/` and are keyed
+//! by the sha256 of the input, the target version and a suffix describing
+//! the other processing options. Each file starts with the sha256 of its payload so a
+//! truncated or corrupted entry is thrown away instead of crashing the
+//! renderer. Folders of other mod versions are deleted on startup as the
+//! conversion code may have changed.
+use crate::config::{ModConfig, CONFIG_DIR};
+use crate::hashing::{sha256, to_hex, Hash};
+use materialbin::MinecraftVersion;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+pub enum Cached {
+    /// The material was already in the target version or could not be read
+    Unchanged,
+    Converted(Vec<u8>),
+}
+
+static CACHE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+pub fn init_material_cache(config: &ModConfig) {
+    if !config.material_cache {
+        return;
+    }
+    let root = Path::new(CONFIG_DIR).join("material_cache");
+    let dir = root.join(env!("CARGO_PKG_VERSION"));
+    if let Err(e) = fs::create_dir_all(&dir) {
+        log::warn!("Failed to create material cache, it is off: {e}");
+        return;
+    }
+    if let Err(e) = remove_stale(&root, &dir) {
+        log::warn!("Failed to clean old material caches: {e}");
+    }
+    let _ = CACHE_DIR.set(dir);
+}
+
+fn remove_stale(root: &Path, current: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(root)? {
+        let path = entry?.path();
+        if path != current && path.is_dir() {
+            log::info!("Removing material cache of another version: {}", path.display());
+            fs::remove_dir_all(&path)?;
+        }
+    }
+    Ok(())
+}
+
+fn entry_path(dir: &Path, input: &Hash, version: MinecraftVersion, options: &str) -> PathBuf {
+    let version: String = version
+        .to_string()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    dir.join(format!("{}_{version}{options}.bin", to_hex(input)))
+}
+
+pub fn load(input: &Hash, version: MinecraftVersion, options: &str) -> Option<Cached> {
+    let dir = CACHE_DIR.get()?;
+    let path = entry_path(dir, input, version, options);
+    let file = fs::read(&path).ok()?;
+    let intact = file.len() >= 32 && file[..32] == sha256(&file[32..]);
+    if !intact {
+        log::warn!("Dropping corrupted material cache entry {}", path.display());
+        let _ = fs::remove_file(&path);
+        return None;
+    }
+    let payload = &file[32..];
+    // Converted materials are never empty, so that means unchanged
+    if payload.is_empty() {
+        return Some(Cached::Unchanged);
+    }
+    Some(Cached::Converted(payload.to_vec()))
+}
+
+pub fn store(input: &Hash, version: MinecraftVersion, options: &str, converted: Option<&[u8]>) {
+    let Some(dir) = CACHE_DIR.get() else {
+        return;
+    };
+    let payload = converted.unwrap_or_default();
+    let mut file = Vec::with_capacity(32 + payload.len());
+    file.extend_from_slice(&sha256(payload));
+    file.extend_from_slice(payload);
+    // Written aside and renamed so readers never see half an entry
+    let path = entry_path(dir, input, version, options);
+    let temp = path.with_extension("tmp");
+    if let Err(e) = fs::write(&temp, file).and_then(|_| fs::rename(&temp, &path)) {
+        log::warn!("Failed to cache converted material: {e}");
+    }
+}
@@ -0,0 +1,184 @@
+//! Json summaries of material.bin files.
+//!
+//! With `debug_materials` on every material going through `process_material`
+//! is logged this way, and bins dropped in `origin_mods/inspect/` get a
+//! `.json` summary next to them at startup. Nothing here touches the game,
+//! so [`inspect_file`] works on any bin on disk.
+use crate::config::{ModConfig, CONFIG_DIR};
+use crate::game_version;
+use materialbin::{
+    bgfx_shader::BgfxShader, CompiledMaterialDefinition, MinecraftVersion, Pass, Variant,
+};
+use scroll::Pread;
+use regex::Regex;
+use serde_json::{json, Map, Value};
+use std::{error::Error, fs, io, path::Path, sync::OnceLock};
+
+/// Summary of a material in whatever version it parses as
+pub fn inspect(data: &[u8]) -> Result<Value, Box<dyn Error>> {
+    for version in materialbin::ALL_VERSIONS {
+        if let Ok(material) = data.pread_with::<CompiledMaterialDefinition>(0, version) {
+            return Ok(summarize(&material, version, data.len()));
+        }
+    }
+    Err("not a material.bin of any known version".into())
+}
+
+pub fn inspect_file(path: &Path) -> Result<Value, Box<dyn Error>> {
+    inspect(&fs::read(path)?)
+}
+
+/// Summarizes every material.bin in `origin_mods/inspect/`
+pub fn inspect_dropped(config: &ModConfig) {
+    if !config.debug_materials {
+        return;
+    }
+    let dir = Path::new(CONFIG_DIR).join("inspect");
+    if let Err(e) = fs::create_dir_all(&dir).and_then(|_| inspect_dir(&dir)) {
+        log::warn!("Failed to inspect materials in {}: {e}", dir.display());
+    }
+}
+
+fn inspect_dir(dir: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_material = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.ends_with(".material.bin"));
+        if !is_material {
+            continue;
+        }
+        match inspect_file(&path) {
+            Ok(summary) => {
+                let json = serde_json::to_vec_pretty(&summary)?;
+                fs::write(path.with_extension("json"), json)?;
+            }
+            Err(e) => log::warn!("[material] Cannot inspect {}: {e}", path.display()),
+        }
+    }
+    Ok(())
+}
+
+/// Logs the summary of a material, `name` is only used for the log line
+pub fn log_material(name: &str, data: &[u8]) {
+    match inspect(data) {
+        Ok(summary) => log::info!("[material] {name}: {summary}"),
+        Err(e) => log::warn!("[material] Cannot inspect {name}: {e}"),
+    }
+}
+
+fn summarize(
+    material: &CompiledMaterialDefinition,
+    version: MinecraftVersion,
+    size: usize,
+) -> Value {
+    let passes: Map<String, Value> = material
+        .passes
+        .iter()
+        .map(|(name, pass)| (name.clone(), summarize_pass(pass)))
+        .collect();
+    json!({
+        "name": material.name,
+        "parent": material.parent_name,
+        "parsed_as": version.to_string(),
+        // What it gets converted to, once the game version is known
+        "target": game_version::current().map(|game| game.material.to_string()),
+        "size": size,
+        "samplers": material.sampler_definitions.keys().collect::<Vec<_>>(),
+        "uniforms": material.property_fields.keys().collect::<Vec<_>>(),
+        "passes": passes,
+    })
+}
+
+fn summarize_pass(pass: &Pass) -> Value {
+    let variants: Vec<Value> = pass.variants.iter().map(summarize_variant).collect();
+    json!({
+        "fallback": pass.fallback_pass,
+        "default_flags": string_map(pass.default_variant.iter()),
+        "variants": variants,
+    })
+}
+
+fn summarize_variant(variant: &Variant) -> Value {
+    let stages: Vec<Value> = variant
+        .shader_codes
+        .iter()
+        .map(|(stage, code)| {
+            // Uniforms and buffers only live in the bgfx blob, leave them out
+            // if it doesn't parse
+            let shader = code.bgfx_shader_data.pread::<BgfxShader>(0).ok();
+            let uniforms = shader.as_ref().map(|shader| {
+                shader
+                    .uniforms
+                    .iter()
+                    .map(|uniform| uniform.name.clone())
+                    .collect::<Vec<_>>()
+            });
+            json!({
+                "stage": stage.stage_name,
+                "platform": stage.platform_name,
+                "size": code.bgfx_shader_data.len(),
+                "inputs": code.shader_inputs.keys().collect::<Vec<_>>(),
+                "uniforms": uniforms,
+                "buffers": shader.as_ref().map(|shader| buffers(&shader.code)),
+            })
+        })
+        .collect();
+    json!({
+        "supported": variant.is_supported,
+        "flags": string_map(variant.flags.iter()),
+        "stages": stages,
+    })
+}
+
+// Storage buffer blocks declared in a text shader, like
+// `layout(std430, binding = 2) readonly buffer InstanceData`
+fn buffers(code: &[u8]) -> Vec<String> {
+    static BUFFER: OnceLock<Regex> = OnceLock::new();
+    let buffer = BUFFER.get_or_init(|| {
+        Regex::new(r"\bbuffer\s+([A-Za-z_]\w*)\s*\{").expect("buffer regex is valid")
+    });
+    let Ok(source) = std::str::from_utf8(code) else {
+        return Vec::new();
+    };
+    buffer
+        .captures_iter(source)
+        .map(|captures| captures[1].to_owned())
+        .collect()
+}
+
+fn string_map<'a>(entries: impl Iterator<Item = (&'a String, &'a String)>) -> Value {
+    entries
+        .map(|(key, value)| (key.clone(), Value::from(value.as_str())))
+        .collect::<Map<_, _>>()
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embedded::{JAVA_CUBEMAP_MATERIALS, NO_FOG_MATERIALS};
+
+    #[test]
+    fn finds_buffer_declarations() {
+        let source = b"layout(std430, binding = 2) readonly buffer InstanceData { vec4 data[]; };\n\
+            buffer Bones{ mat4 bones[]; };\n\
+            uniform vec4 buffer_size;\n";
+        assert_eq!(buffers(source), ["InstanceData", "Bones"]);
+        assert!(buffers(b"\x03\x02\x23\x07").is_empty());
+    }
+
+    #[test]
+    fn inspects_shipped_materials() {
+        for embedded in NO_FOG_MATERIALS.iter().chain(&JAVA_CUBEMAP_MATERIALS) {
+            let path = embedded.path;
+            let summary = inspect(embedded.get().unwrap()).unwrap_or_else(|e| panic!("{path}: {e}"));
+            let file_name = path.rsplit('/').next().unwrap();
+            let name = summary["name"].as_str().unwrap();
+            assert!(file_name.starts_with(name), "{file_name} is named {name}");
+            assert!(summary["parsed_as"].is_string(), "{file_name}");
+            assert!(!summary["passes"].as_object().unwrap().is_empty(), "{file_name}");
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! Patches for the definition of a material rather than its shaders.
+//!
+//! Files in `origin_mods/material_patches/` are loaded as a [`PatchSet`],
+//! each patch can set uniform defaults, remove passes and change the flags of
+//! a pass and its variants:
+//!
+//! ```json
+//! [{
+//!     "material": "RenderChunk.material.bin",
+//!     "uniforms": { "FogColor": [0.6, 0.8, 1.0, 1.0] },
+//!     "remove_passes": ["DepthOnly"],
+//!     "passes": {
+//!         "Transparent": {
+//!             "flags": { "Fog": "Disabled" },
+//!             "variants": [{ "when": { "Instancing": "On" }, "supported": false }]
+//!         }
+//!     }
+//! }]
+//! ```
+//!
+//! Uniform values replace the default vector or matrix, so their count has to
+//! match it (4, 9 or 16).
+use crate::patch_set::PatchSet;
+use materialbin::{CompiledMaterialDefinition, Pass, PropertyField};
+use serde::Deserialize;
+use std::{collections::HashMap, sync::OnceLock};
+
+#[derive(Deserialize)]
+struct MaterialPatch {
+    /// File name of the material, like `RenderChunk.material.bin`
+    material: String,
+    #[serde(default)]
+    uniforms: HashMap<String, Vec<f32>>,
+    #[serde(default)]
+    remove_passes: Vec<String>,
+    /// Keyed by pass name
+    #[serde(default)]
+    passes: HashMap<String, PassPatch>,
+}
+
+#[derive(Deserialize)]
+struct PassPatch {
+    /// New default values of flags the pass already has
+    #[serde(default)]
+    flags: HashMap<String, String>,
+    #[serde(default)]
+    variants: Vec<VariantPatch>,
+}
+
+#[derive(Deserialize)]
+struct VariantPatch {
+    /// Flag values a variant needs to be changed, every variant when empty
+    #[serde(default)]
+    when: HashMap<String, String>,
+    supported: Option<bool>,
+    #[serde(default)]
+    flags: HashMap<String, String>,
+}
+
+static PATCHES: OnceLock<PatchSet<MaterialPatch>> = OnceLock::new();
+
+pub fn init_material_patches() {
+    let _ = PATCHES.set(PatchSet::load("material_patches", |patch| &patch.material));
+}
+
+/// Whether any material patch targets this material, takes any path to it
+pub fn has_patches(name: &str) -> bool {
+    PATCHES.get().is_some_and(|set| set.get(name).is_some())
+}
+
+pub fn fingerprint() -> Option<&'static str> {
+    PATCHES.get().and_then(PatchSet::fingerprint)
+}
+
+/// Applies the patches for `name` to `material`, returns whether anything changed
+pub fn patch_material(name: &str, material: &mut CompiledMaterialDefinition) -> bool {
+    let Some(patches) = PATCHES.get().and_then(|set| set.get(name)) else {
+        return false;
+    };
+    let mut changes = 0;
+    for patch in patches {
+        changes += apply(name, patch, material);
+    }
+    if changes > 0 {
+        log::info!("[material] {name}: made {changes} changes");
+    }
+    changes > 0
+}
+
+fn apply(name: &str, patch: &MaterialPatch, material: &mut CompiledMaterialDefinition) -> usize {
+    let mut changes = 0;
+    for (uniform, values) in &patch.uniforms {
+        let Some(field) = material.property_fields.get_mut(uniform.as_str()) else {
+            log::warn!("[material] {name} has no uniform {uniform}");
+            continue;
+        };
+        match set_default(field, values) {
+            Ok(()) => changes += 1,
+            Err(e) => log::warn!("[material] {name}: cannot set {uniform}, {e}"),
+        }
+    }
+    for pass in &patch.remove_passes {
+        match material.passes.remove(pass.as_str()) {
+            Some(_) => changes += 1,
+            None => log::warn!("[material] {name} has no pass {pass} to remove"),
+        }
+    }
+    for (pass_name, pass_patch) in &patch.passes {
+        match material.passes.get_mut(pass_name.as_str()) {
+            Some(pass) => changes += apply_pass(name, pass_name, pass_patch, pass),
+            None => log::warn!("[material] {name} has no pass {pass_name}"),
+        }
+    }
+    changes
+}
+
+fn apply_pass(name: &str, pass_name: &str, patch: &PassPatch, pass: &mut Pass) -> usize {
+    let mut changes = 0;
+    for (flag, value) in &patch.flags {
+        match pass.default_variant.get_mut(flag.as_str()) {
+            Some(current) => {
+                current.clone_from(value);
+                changes += 1;
+            }
+            None => log::warn!("[material] {name}: pass {pass_name} has no flag {flag}"),
+        }
+    }
+    for toggle in &patch.variants {
+        let matching = pass.variants.iter_mut().filter(|variant| {
+            toggle
+                .when
+                .iter()
+                .all(|(flag, value)| variant.flags.get(flag.as_str()) == Some(value))
+        });
+        let mut matched = 0;
+        for variant in matching {
+            if let Some(supported) = toggle.supported {
+                variant.is_supported = supported;
+            }
+            for (flag, value) in &toggle.flags {
+                if let Some(current) = variant.flags.get_mut(flag.as_str()) {
+                    current.clone_from(value);
+                }
+            }
+            matched += 1;
+        }
+        if matched == 0 {
+            log::warn!(
+                "[material] {name}: no variant of {pass_name} matches {:?}",
+                toggle.when
+            );
+        }
+        changes += matched;
+    }
+    changes
+}
+
+// The default is whichever of vector or matrix data the uniform has
+fn set_default(field: &mut PropertyField, values: &[f32]) -> Result<(), String> {
+    let default: &mut [f32] = if let Some(vector) = field.vector_data.as_mut() {
+        vector
+    } else if let Some(matrix) = field.matrix_3x3_data.as_mut() {
+        matrix
+    } else if let Some(matrix) = field.matrix_4x4_data.as_mut() {
+        matrix
+    } else {
+        return Err("it has no default value".to_owned());
+    };
+    if default.len() != values.len() {
+        return Err(format!(
+            "expected {} values, got {}",
+            default.len(),
+            values.len()
+        ));
+    }
+    default.copy_from_slice(values);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embedded::{JAVA_CUBEMAP_MATERIALS, NO_FOG_MATERIALS};
+    use scroll::Pread;
+    use serde_json::json;
+
+    // File name and bytes of every material the mod ships
+    fn shipped_materials() -> impl Iterator<Item = (&'static str, &'static [u8])> {
+        NO_FOG_MATERIALS.iter().chain(&JAVA_CUBEMAP_MATERIALS).map(|embedded| {
+            let name = embedded.path.rsplit('/').next().unwrap();
+            (name, embedded.get().expect("embedded material is readable"))
+        })
+    }
+
+    fn parse(data: &[u8]) -> CompiledMaterialDefinition {
+        materialbin::ALL_VERSIONS
+            .into_iter()
+            .find_map(|version| data.pread_with(0, version).ok())
+            .expect("material parses in some version")
+    }
+
+    // One uniform default, one removed pass and one pass flag, picked from
+    // whatever the material has
+    fn patch_for(name: &str, material: &CompiledMaterialDefinition) -> Option<MaterialPatch> {
+        let (uniform, field) = material
+            .property_fields
+            .iter()
+            .find(|(_, field)| field.vector_data.is_some())?;
+        let values: Vec<f32> = (0..field.vector_data?.len()).map(|i| i as f32 + 0.5).collect();
+        let mut passes = material.passes.iter();
+        let (flagged, pass) = passes.find(|(_, pass)| !pass.default_variant.is_empty())?;
+        let flag = pass.default_variant.keys().next()?;
+        let removed = material.passes.keys().find(|pass| *pass != flagged)?;
+        let patch = json!({
+            "material": name,
+            "uniforms": { uniform.as_str(): values },
+            "remove_passes": [removed],
+            "passes": { flagged.as_str(): { "flags": { flag.as_str(): "Patched" } } },
+        });
+        Some(serde_json::from_value(patch).unwrap())
+    }
+
+    #[test]
+    fn patched_materials_round_trip() {
+        let mut tested = 0;
+        for (name, data) in shipped_materials() {
+            let original = parse(data);
+            let Some(patch) = patch_for(name, &original) else {
+                continue;
+            };
+            let mut material = parse(data);
+            assert_eq!(apply(name, &patch, &mut material), 3, "{name}");
+            for version in materialbin::ALL_VERSIONS {
+                let mut output = Vec::new();
+                material.write(&mut output, version).unwrap();
+                let read: CompiledMaterialDefinition = output
+                    .pread_with(0, version)
+                    .unwrap_or_else(|e| panic!("{name} as {version}: {e}"));
+                for (uniform, values) in &patch.uniforms {
+                    let field = read.property_fields.get(uniform.as_str()).unwrap();
+                    assert_eq!(
+                        field.vector_data.as_ref().map(|v| &v[..]),
+                        Some(values.as_slice()),
+                        "{name} as {version}"
+                    );
+                }
+                for pass in &patch.remove_passes {
+                    assert!(!read.passes.contains_key(pass.as_str()), "{name} as {version}");
+                }
+                for (pass, pass_patch) in &patch.passes {
+                    let flags = &read.passes.get(pass.as_str()).unwrap().default_variant;
+                    for (flag, value) in &pass_patch.flags {
+                        assert_eq!(flags.get(flag.as_str()), Some(value), "{name} as {version}");
+                    }
+                }
+            }
+            tested += 1;
+        }
+        assert!(tested > 0, "no shipped material has a uniform and two passes");
+    }
+}
@@ -0,0 +1,65 @@
+//! Real file descriptors for bytes that only live in memory.
+use crate::config::CONFIG_DIR;
+use std::{
+    fs::{self, File},
+    io::{self, Seek, Write},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Puts `data` in a memfd, or an unlinked temp file on kernels without one.
+/// The returned fd is positioned at 0
+pub fn fd_for_bytes(data: &[u8]) -> io::Result<OwnedFd> {
+    let mut file = match memfd_create() {
+        Ok(file) => file,
+        Err(e) => {
+            log::warn!("memfd_create failed, using a temp file: {e}");
+            temp_file()?
+        }
+    };
+    file.write_all(data)?;
+    file.rewind()?;
+    Ok(file.into())
+}
+
+fn memfd_create() -> io::Result<File> {
+    // Called through syscall as bionic only has the wrapper since api 30
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_memfd_create,
+            c"origin_asset".as_ptr(),
+            libc::MFD_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(fd as libc::c_int) })
+}
+
+fn temp_file() -> io::Result<File> {
+    let dir = Path::new(CONFIG_DIR).join("tmp");
+    fs::create_dir_all(&dir)?;
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let path = dir.join(format!("asset_{}_{id}", std::process::id()));
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)?;
+    // Only the fd keeps it alive from now on
+    fs::remove_file(&path)?;
+    Ok(file)
+}
+
+/// A new fd for the same file with its own offset, so whoever
+/// gets it can't move the offset of other users
+pub fn reopen(fd: &OwnedFd) -> io::Result<OwnedFd> {
+    match File::open(format!("/proc/self/fd/{}", fd.as_raw_fd())) {
+        Ok(file) => Ok(file.into()),
+        Err(_) => fd.try_clone(),
+    }
+}
@@ -0,0 +1,73 @@
+//! Loose file overlay in `origin_mods/overrides/`.
+//!
+//! A file at `overrides/<canonical apk path>` is served instead of the apk asset,
+//! e.g. `overrides/resource_packs/vanilla/splashes.json`.
+use crate::asset_path::{child_name, AssetPath};
+use crate::config::CONFIG_DIR;
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+// Canonical paths of every file in the overrides folder, filled once at startup
+static OVERRIDES: OnceLock<HashSet<String>> = OnceLock::new();
+
+pub fn overrides_dir() -> PathBuf {
+    Path::new(CONFIG_DIR).join("overrides")
+}
+
+pub fn init_overrides() {
+    let dir = overrides_dir();
+    if let Err(e) = fs::create_dir_all(&dir) {
+        log::warn!("Failed to create overrides directory: {e}");
+    }
+    let mut index = HashSet::new();
+    if let Err(e) = index_dir(&dir, &dir, &mut index) {
+        log::warn!("Failed to index overrides: {e}");
+    }
+    log::info!("Found {} override files", index.len());
+    let _ = OVERRIDES.set(index);
+}
+
+fn index_dir(root: &Path, dir: &Path, index: &mut HashSet<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            index_dir(root, &path, index)?;
+            continue;
+        }
+        let Some(relative) = path.strip_prefix(root).ok().and_then(|p| p.to_str()) else {
+            log::warn!("Skipping override with weird path: {}", path.display());
+            continue;
+        };
+        let Some(asset) = AssetPath::new(relative) else {
+            log::warn!("Skipping override outside the apk: {}", path.display());
+            continue;
+        };
+        index.insert(asset.as_str().to_owned());
+    }
+    Ok(())
+}
+
+pub fn has_override(path: &AssetPath) -> bool {
+    OVERRIDES
+        .get()
+        .is_some_and(|index| index.contains(path.as_str()))
+}
+
+pub fn read_override(path: &AssetPath) -> io::Result<Vec<u8>> {
+    fs::read(overrides_dir().join(path.as_str()))
+}
+
+/// Names of override files directly inside a canonical folder
+pub fn override_names_in(dir: &str) -> Vec<&'static str> {
+    let Some(index) = OVERRIDES.get() else {
+        return Vec::new();
+    };
+    index
+        .iter()
+        .filter_map(|path| child_name(dir, path))
+        .collect()
+}
@@ -0,0 +1,175 @@
+//! In memory cache of patch results.
+//!
+//! Patching a big json file means parsing and serializing it again, and the
+//! game opens some files several times per session. Results are keyed by the
+//! canonical path and the hash of the original, so an updated original never
+//! gets a stale result, and the least recently used ones go first once the
+//! cache is over `patch_cache_mb`.
+use crate::asset_path::AssetPath;
+use crate::hashing::Hash;
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+struct Entry {
+    data: Arc<[u8]>,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct PatchCache {
+    entries: HashMap<(String, Hash), Entry>,
+    bytes: usize,
+    // Bumped on every access, the smallest `last_used` is the LRU entry
+    clock: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+static CACHE: Lazy<Mutex<PatchCache>> = Lazy::new(Default::default);
+
+impl PatchCache {
+    fn log_stats(&self) {
+        log::info!(
+            "Patch cache: {} hits, {} misses, {} evictions, {} entries using {} bytes",
+            self.hits,
+            self.misses,
+            self.evictions,
+            self.entries.len(),
+            self.bytes
+        );
+    }
+
+    fn evict_until(&mut self, limit: usize) {
+        while self.bytes > limit {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&oldest) {
+                self.bytes -= entry.data.len();
+                self.evictions += 1;
+            }
+        }
+    }
+
+    fn get(&mut self, key: &(String, Hash)) -> Option<Arc<[u8]>> {
+        self.clock += 1;
+        let clock = self.clock;
+        let found = self.entries.get_mut(key).map(|entry| {
+            entry.last_used = clock;
+            entry.data.clone()
+        });
+        match found {
+            Some(_) => self.hits += 1,
+            None => self.misses += 1,
+        }
+        found
+    }
+
+    fn insert(&mut self, key: (String, Hash), data: Arc<[u8]>, limit: usize) {
+        self.clock += 1;
+        let entry = Entry {
+            data: data.clone(),
+            last_used: self.clock,
+        };
+        self.bytes += data.len();
+        if let Some(old) = self.entries.insert(key, entry) {
+            self.bytes -= old.data.len();
+        }
+        self.evict_until(limit);
+    }
+}
+
+fn lock() -> std::sync::MutexGuard<'static, PatchCache> {
+    CACHE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// A previous result of patching this exact original
+pub fn get(path: &AssetPath, original: &Hash) -> Option<Arc<[u8]>> {
+    let mut cache = lock();
+    let found = cache.get(&(path.as_str().to_owned(), *original));
+    if found.is_some() {
+        cache.log_stats();
+    }
+    found
+}
+
+/// Stores a result, `limit` is the cache size in bytes, 0 turns caching off
+pub fn insert(path: &AssetPath, original: Hash, data: Vec<u8>, limit: usize) -> Arc<[u8]> {
+    let data: Arc<[u8]> = data.into();
+    if limit == 0 || data.len() > limit {
+        return data;
+    }
+    let mut cache = lock();
+    cache.insert((path.as_str().to_owned(), original), data.clone(), limit);
+    cache.log_stats();
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing::sha256;
+
+    fn key(path: &str) -> (String, Hash) {
+        (path.to_owned(), sha256(path.as_bytes()))
+    }
+
+    fn data(len: usize) -> Arc<[u8]> {
+        vec![0; len].into()
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = PatchCache::default();
+        cache.insert(key("a"), data(4), 12);
+        cache.insert(key("b"), data(4), 12);
+        cache.insert(key("c"), data(4), 12);
+        // Touching a makes b the oldest
+        assert!(cache.get(&key("a")).is_some());
+        cache.insert(key("d"), data(4), 12);
+        assert!(cache.get(&key("b")).is_none());
+        for kept in ["a", "c", "d"] {
+            assert!(cache.get(&key(kept)).is_some(), "{kept}");
+        }
+        assert_eq!((cache.bytes, cache.evictions), (12, 1));
+    }
+
+    #[test]
+    fn replacing_an_entry_keeps_the_size_right() {
+        let mut cache = PatchCache::default();
+        cache.insert(key("a"), data(8), 12);
+        cache.insert(key("a"), data(2), 12);
+        assert_eq!((cache.bytes, cache.entries.len()), (2, 1));
+    }
+
+    #[test]
+    fn counts_hits_and_misses() {
+        let mut cache = PatchCache::default();
+        assert!(cache.get(&key("a")).is_none());
+        cache.insert(key("a"), data(1), 12);
+        assert!(cache.get(&key("a")).is_some());
+        assert!(cache.get(&key("a")).is_some());
+        // Same path with another original is a different entry
+        assert!(cache.get(&("a".to_owned(), sha256(b"other"))).is_none());
+        assert_eq!((cache.hits, cache.misses), (2, 2));
+    }
+
+    #[test]
+    fn disabled_and_oversize_results_bypass_the_cache() {
+        let path = AssetPath::new("resource_packs/vanilla/patch_cache_test.json").unwrap();
+        let hash = sha256(b"patch_cache_test");
+        assert_eq!(&*insert(&path, hash, vec![1; 4], 0), [1; 4]);
+        assert!(insert(&path, hash, Vec::new(), 0).is_empty());
+        assert_eq!(&*insert(&path, hash, vec![2; 16], 8), [2; 16]);
+        assert!(get(&path, &hash).is_none());
+    }
+}
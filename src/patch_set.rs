@@ -0,0 +1,87 @@
+//! Folders of material patch files, shared by shader and material patches.
+//!
+//! Every file in the folder holds a [`jsonc`] array of patches, each patch
+//! names the material file it is for. A fingerprint of all loaded files keys
+//! the material cache, so edited patches never serve stale results.
+use crate::config::CONFIG_DIR;
+use crate::hashing::{sha256, to_hex};
+use crate::jsonc;
+use serde::de::DeserializeOwned;
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+pub struct PatchSet<T> {
+    // Keyed by material file name
+    patches: HashMap<String, Vec<T>>,
+    fingerprint: String,
+}
+
+impl<T: DeserializeOwned> PatchSet<T> {
+    /// Loads `origin_mods/<folder>/`, `material` gives the file a patch is for
+    pub fn load(folder: &str, material: fn(&T) -> &str) -> Self {
+        let dir = Path::new(CONFIG_DIR).join(folder);
+        if let Err(e) = fs::create_dir_all(&dir) {
+            log::warn!("Failed to create {folder} directory: {e}");
+        }
+        let mut files = Vec::new();
+        if let Err(e) = read_files(&dir, &mut files) {
+            log::warn!("Failed to load {folder}: {e}");
+        }
+        // Sorted so the patch order and fingerprint don't depend on the file system
+        files.sort();
+        let mut all = Vec::new();
+        let mut patches: HashMap<String, Vec<T>> = HashMap::new();
+        for (path, data) in files {
+            let parsed = jsonc::from_slice(&data)
+                .map_err(Box::<dyn std::error::Error>::from)
+                .and_then(|json| Ok(serde_json::from_value::<Vec<T>>(json)?));
+            match parsed {
+                Ok(parsed) => {
+                    log::info!("Loaded {} {folder} from {}", parsed.len(), path.display());
+                    for patch in parsed {
+                        patches
+                            .entry(material(&patch).to_owned())
+                            .or_default()
+                            .push(patch);
+                    }
+                    all.extend_from_slice(&data);
+                }
+                Err(e) => log::warn!("Skipping {}: {e}", path.display()),
+            }
+        }
+        log::info!("Loaded {folder} for {} materials", patches.len());
+        PatchSet {
+            patches,
+            fingerprint: to_hex(&sha256(&all))[..16].to_owned(),
+        }
+    }
+}
+
+impl<T> PatchSet<T> {
+    /// Patches for a material, takes any path to it
+    pub fn get(&self, name: &str) -> Option<&[T]> {
+        let file_name = name.rsplit('/').next().unwrap_or(name);
+        self.patches.get(file_name).map(Vec::as_slice)
+    }
+
+    /// Identifies the loaded patches, None when there are none
+    pub fn fingerprint(&self) -> Option<&str> {
+        (!self.patches.is_empty()).then_some(self.fingerprint.as_str())
+    }
+}
+
+fn read_files(dir: &Path, files: &mut Vec<(PathBuf, Vec<u8>)>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            read_files(&path, files)?;
+        } else {
+            let data = fs::read(&path)?;
+            files.push((path, data));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,440 @@
+//! Declarative asset rules evaluated by `aasset::open`.
+//!
+//! Every feature that touches an asset is described as an [`AssetRule`]:
+//! a path matcher, a condition on [`ModConfig`] and an [`Action`].
+//! Nothing in here touches the NDK so rules can be checked on the host.
+use crate::archives::has_archive_entry;
+use crate::asset_path::{child_name, glob_match, AssetPath, PackRoot};
+use crate::config::{get_config, ModConfig};
+use crate::embedded::{
+    Embedded, CLOUDS_PNG, FIRST_PERSON_JSON, JAVA_CUBEMAP_MATERIALS, LOADING_MESSAGES_JSON,
+    NO_FOG_MATERIALS, SKINS_JSON, SPLASHES_JSON, THIRD_PERSON_FRONT_JSON, THIRD_PERSON_JSON,
+};
+use crate::guard::Policy;
+use crate::json_patch::{apply_json_patches, has_json_patches};
+use crate::overrides::has_override;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+pub type PatchFn = fn(&AssetPath, &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>>;
+
+/// How a rule decides if it applies to a path,
+/// everything is compared against the canonical [`AssetPath`]
+pub enum Matcher {
+    /// Exactly this path
+    Exact(&'static str),
+    /// Any of these globs
+    Glob(&'static [&'static str]),
+    /// Any of these globs inside every pack of a kind, they are matched
+    /// against the path relative to the pack
+    InPack(PackRoot, &'static [&'static str]),
+    /// Everything inside this folder
+    Prefix(&'static str),
+    /// Anything that does not fit the above
+    Custom(fn(&AssetPath) -> bool),
+}
+
+impl Matcher {
+    pub fn matches(&self, path: &AssetPath) -> bool {
+        match self {
+            Matcher::Exact(exact) => path.as_str() == *exact,
+            Matcher::Glob(globs) => globs.iter().any(|glob| glob_match(glob, path.as_str())),
+            Matcher::InPack(root, globs) => {
+                path.root() == *root && globs.iter().any(|glob| glob_match(glob, path.relative()))
+            }
+            Matcher::Prefix(prefix) => path.as_str().starts_with(prefix),
+            Matcher::Custom(func) => func(path),
+        }
+    }
+}
+
+/// What happens to the asset once a rule matched
+pub enum Action {
+    /// Serve this embedded file instead of the apk file
+    Replace(&'static Embedded),
+    /// Pretend the file does not exist
+    Block,
+    /// Feed the original bytes through a function, needs the apk file to exist
+    Patch(PatchFn),
+    /// Load the file from the active resource packs, see [`PackMapping`]
+    Redirect(&'static PackMapping),
+    /// Serve the file from the user overrides
+    Override(Source),
+}
+
+/// Where user overrides come from
+pub enum Source {
+    /// Loose files in `origin_mods/overrides/`
+    Folder,
+    /// Archives in `origin_mods/packs/`
+    Archive,
+}
+
+/// An apk folder that resource packs can provide files for,
+/// set in `pack_mappings` in the config
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PackMapping {
+    /// Rule name in logs, traces and guards
+    pub name: String,
+    /// Canonical apk folder, ending in `/`
+    pub apk: String,
+    /// Folder inside the resource packs
+    pub pack: String,
+    /// Convert pack materials to the running game version
+    #[serde(default = "enabled")]
+    pub convert_materials: bool,
+    /// Merge pack json into the apk file instead of replacing it
+    #[serde(default)]
+    pub merge_json: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl PackMapping {
+    fn new(name: &str, apk: &str, pack: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            apk: apk.to_owned(),
+            pack: pack.to_owned(),
+            convert_materials: true,
+            merge_json: false,
+        }
+    }
+
+    /// Puts both folders in the form redirects expect, canonical and ending
+    /// in `/`. Returns false for a mapping that would cover the whole apk
+    pub fn normalize(&mut self) -> bool {
+        let Some(apk) = AssetPath::new(&self.apk) else {
+            log::warn!("Pack mapping {} points outside the apk, ignoring it", self.name);
+            return false;
+        };
+        let apk = apk.as_str().trim_end_matches('/').to_owned();
+        if apk.is_empty() {
+            log::warn!("Pack mapping {} has no apk folder, ignoring it", self.name);
+            return false;
+        }
+        self.apk = format!("{apk}/");
+        let pack = self.pack.trim_matches('/');
+        self.pack = if pack.is_empty() {
+            String::new()
+        } else {
+            format!("{pack}/")
+        };
+        true
+    }
+}
+
+/// The folders packs could always redirect
+pub fn default_pack_mappings() -> Vec<PackMapping> {
+    vec![
+        PackMapping::new("hbui_pack", "gui/dist/hbui/", "hbui/"),
+        PackMapping::new("persona_pack", "skin_packs/persona/", "persona/"),
+        PackMapping::new("renderer_pack", "renderer/", "renderer/"),
+        PackMapping::new("cameras_pack", "resource_packs/vanilla/cameras/", "vanilla_cameras/"),
+    ]
+}
+
+/// Mappings from the config on top of the defaults, one named like a
+/// default takes its place and the others come after the defaults
+pub fn with_default_mappings(configured: Vec<PackMapping>) -> Vec<PackMapping> {
+    let mut mappings = default_pack_mappings();
+    for mapping in configured {
+        match mappings.iter_mut().find(|default| default.name == mapping.name) {
+            Some(default) => *default = mapping,
+            None => mappings.push(mapping),
+        }
+    }
+    mappings
+}
+
+pub struct AssetRule {
+    pub name: &'static str,
+    /// Lower runs first
+    pub priority: i32,
+    pub matcher: Matcher,
+    pub enabled: fn(&ModConfig) -> bool,
+    pub action: Action,
+    /// Check the original before replacing it, see [`crate::guard`]
+    pub guard: Option<Policy>,
+}
+
+impl AssetRule {
+    pub fn applies(&self, path: &AssetPath, config: &ModConfig, exists: bool) -> bool {
+        if matches!(self.action, Action::Patch(_)) && !exists {
+            return false;
+        }
+        (self.enabled)(config) && self.matcher.matches(path)
+    }
+
+    /// Splits a path into its pack mapping and the path relative to the
+    /// mapped folder, only for redirect rules
+    pub fn redirect<'a>(&self, path: &'a AssetPath) -> Option<(&'static PackMapping, &'a str)> {
+        let Action::Redirect(mapping) = self.action else {
+            return None;
+        };
+        let file = path.as_str().strip_prefix(mapping.apk.as_str())?;
+        Some((mapping, file))
+    }
+}
+
+/// Finds the first enabled rule matching this path
+pub fn find_rule(path: &AssetPath, config: &ModConfig, exists: bool) -> Option<&'static AssetRule> {
+    RULES.iter().find(|rule| rule.applies(path, config, exists))
+}
+
+pub fn all() -> &'static [AssetRule] {
+    &RULES
+}
+
+/// Names of files directly inside a canonical folder that enabled rules
+/// serve without needing the apk, only exact matchers can be listed
+pub fn injected_names_in(dir: &str, config: &ModConfig) -> Vec<&'static str> {
+    RULES
+        .iter()
+        .filter(|rule| matches!(rule.action, Action::Replace(_)) && (rule.enabled)(config))
+        .filter_map(|rule| match rule.matcher {
+            Matcher::Exact(path) => child_name(dir, path),
+            _ => None,
+        })
+        .collect()
+}
+
+static RULES: Lazy<Vec<AssetRule>> = Lazy::new(|| {
+    let mut rules = default_rules();
+    rules.extend(pack_rules(get_config()));
+    // Stable sort so rules with the same priority keep declaration order
+    rules.sort_by_key(|rule| rule.priority);
+    rules
+});
+
+macro_rules! rule {
+    ($name:expr, $priority:expr, $matcher:expr, $enabled:expr => $action:expr) => {
+        rule!($name, $priority, $matcher, $enabled => $action, guard = None)
+    };
+    ($name:expr, $priority:expr, $matcher:expr, $enabled:expr => $action:expr, guard = $guard:expr) => {
+        AssetRule {
+            name: $name,
+            priority: $priority,
+            matcher: $matcher,
+            enabled: $enabled,
+            action: $action,
+            guard: $guard,
+        }
+    };
+}
+
+// Priorities, blocking always wins over anything else. Json patches only
+// get the files no other rule wants, replaced and redirected json has the
+// patches applied on top when it is served
+const BLOCK: i32 = 0;
+const OVERRIDE: i32 = 50;
+const REPLACE: i32 = 100;
+const MATERIAL: i32 = 300;
+const REDIRECT: i32 = 400;
+const PATCH: i32 = 500;
+
+fn default_rules() -> Vec<AssetRule> {
+    let mut rules = vec![
+        rule!("persona_block", BLOCK, Matcher::Glob(&BLOCKED_PERSONAS), |c| c.classic_skins => Action::Block),
+        rule!("particles_block", BLOCK, Matcher::InPack(PackRoot::ResourcePack, &PARTICLE_GLOBS), |c| c.particles_disabler => Action::Block),
+        rule!("user_override", OVERRIDE, Matcher::Custom(has_override), |_| true => Action::Override(Source::Folder)),
+        rule!("archive_override", OVERRIDE, Matcher::Custom(has_archive_entry), |_| true => Action::Override(Source::Archive)),
+        rule!("splashes", REPLACE, Matcher::Exact("resource_packs/vanilla/splashes.json"), |_| true => Action::Replace(&SPLASHES_JSON)),
+        rule!("loading_messages", REPLACE, Matcher::Glob(&["resource_packs/vanilla/**/loading_messages.json"]), |_| true => Action::Replace(&LOADING_MESSAGES_JSON)),
+        rule!("java_clouds", REPLACE, Matcher::Exact("resource_packs/vanilla/textures/environment/clouds.png"), |c| c.java_clouds => Action::Replace(&CLOUDS_PNG)),
+        rule!("classic_skins_json", REPLACE, Matcher::Exact("skin_packs/vanilla/skins.json"), |c| c.classic_skins => Action::Replace(&SKINS_JSON)),
+        rule!("first_person_camera", REPLACE, Matcher::Exact("resource_packs/vanilla/cameras/first_person.json"), |c| c.no_hurt_cam => Action::Replace(&FIRST_PERSON_JSON), guard = Some(Policy::Merge)),
+        rule!("third_person_camera", REPLACE, Matcher::Exact("resource_packs/vanilla/cameras/third_person.json"), |c| c.no_hurt_cam => Action::Replace(&THIRD_PERSON_JSON), guard = Some(Policy::Merge)),
+        rule!("third_person_front_camera", REPLACE, Matcher::Exact("resource_packs/vanilla/cameras/third_person_front.json"), |c| c.no_hurt_cam => Action::Replace(&THIRD_PERSON_FRONT_JSON), guard = Some(Policy::Merge)),
+        rule!("json_patches", PATCH, Matcher::Custom(has_json_patches), |_| true => Action::Patch(apply_json_patches)),
+    ];
+    // One rule per bin in the feature folders. Both ship LegacyCubemap, java
+    // cubemap goes first so enabling it wins over the no fog copy
+    for material in &JAVA_CUBEMAP_MATERIALS {
+        rules.push(rule!("java_cubemap", MATERIAL, Matcher::Exact(material.path), |c| c.java_cubemap => Action::Replace(material), guard = Some(Policy::Warn)));
+    }
+    for material in &NO_FOG_MATERIALS {
+        rules.push(rule!("no_fog_materials", MATERIAL, Matcher::Exact(material.path), |c| c.no_fog => Action::Replace(material), guard = Some(Policy::Warn)));
+    }
+    rules
+}
+
+// Redirects from the config, they come last as they cover whole folders
+fn pack_rules(config: &'static ModConfig) -> impl Iterator<Item = AssetRule> {
+    config.pack_mappings.iter().map(|mapping| {
+        rule!(mapping.name.as_str(), REDIRECT, Matcher::Prefix(mapping.apk.as_str()), |_| true => Action::Redirect(mapping))
+    })
+}
+
+const BLOCKED_PERSONAS: [&str; 7] = [
+    "skin_packs/persona/**/08_Kai_Dcast.json",
+    "skin_packs/persona/**/07_Zuri_Dcast.json",
+    "skin_packs/persona/**/06_Efe_Dcast.json",
+    "skin_packs/persona/**/05_Makena_Dcast.json",
+    "skin_packs/persona/**/04_Sunny_Dcast.json",
+    "skin_packs/persona/**/03_Ari_Dcast.json",
+    "skin_packs/persona/**/02_ Noor_Dcast.json",
+];
+
+// The particle definitions and their textures, in every vanilla pack
+const PARTICLE_GLOBS: [&str; 3] = [
+    "particles/**",
+    "textures/particle/**",
+    "textures/particles/**",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::init_test_config;
+
+    fn rule_for(path: &str, config: &ModConfig, exists: bool) -> Option<&'static str> {
+        init_test_config();
+        find_rule(&AssetPath::new(path).unwrap(), config, exists).map(|rule| rule.name)
+    }
+
+    fn no_features() -> ModConfig {
+        ModConfig {
+            no_hurt_cam: false,
+            ..ModConfig::default()
+        }
+    }
+
+    fn all_features() -> ModConfig {
+        ModConfig {
+            no_fog: true,
+            java_cubemap: true,
+            java_clouds: true,
+            classic_skins: true,
+            particles_disabler: true,
+            no_hurt_cam: true,
+            ..ModConfig::default()
+        }
+    }
+
+    #[test]
+    fn rules_are_sorted_by_priority() {
+        init_test_config();
+        assert!(all().windows(2).all(|pair| pair[0].priority <= pair[1].priority));
+    }
+
+    #[test]
+    fn features_follow_the_config() {
+        let off = no_features();
+        let on = all_features();
+        let clouds = "assets/resource_packs/vanilla/textures/environment/clouds.png";
+        assert_eq!(rule_for(clouds, &off, true), None);
+        assert_eq!(rule_for(clouds, &on, true), Some("java_clouds"));
+        let camera = "assets/resource_packs/vanilla/cameras/first_person.json";
+        assert_eq!(rule_for(camera, &off, true), Some("cameras_pack"));
+        assert_eq!(rule_for(camera, &on, true), Some("first_person_camera"));
+        let persona = "assets/skin_packs/persona/x/08_Kai_Dcast.json";
+        assert_eq!(rule_for(persona, &off, true), Some("persona_pack"));
+        assert_eq!(rule_for(persona, &on, true), Some("persona_block"));
+    }
+
+    #[test]
+    fn rules_without_a_feature_always_apply() {
+        let off = no_features();
+        assert_eq!(rule_for("assets/resource_packs/vanilla/splashes.json", &off, true), Some("splashes"));
+        assert_eq!(
+            rule_for("resource_packs/vanilla/texts/loading_messages.json", &off, false),
+            Some("loading_messages")
+        );
+        assert_eq!(rule_for("assets/resource_packs/vanilla/models/mobs.json", &off, true), None);
+    }
+
+    #[test]
+    fn blocking_wins() {
+        let on = all_features();
+        let particle = "assets/resource_packs/vanilla/particles/smoke.json";
+        assert_eq!(rule_for(particle, &on, true), Some("particles_block"));
+        assert_eq!(rule_for(particle, &no_features(), true), None);
+    }
+
+    #[test]
+    fn apk_paths_find_their_rule() {
+        let on = all_features();
+        let corpus = [
+            ("assets/resource_packs/vanilla/particles/campfire_smoke.json", Some("particles_block")),
+            ("resource_packs/vanilla_1.20.50/textures/particle/particles.png", Some("particles_block")),
+            ("assets/resource_packs/chemistry/particles/sparkler.json", Some("particles_block")),
+            ("resource_packs/vanilla/textures/particle_effects/x.png", None),
+            ("assets/skin_packs/persona/03_Ari_Dcast.json", Some("persona_block")),
+            ("assets/skin_packs/persona/01_Steve.json", Some("persona_pack")),
+            ("skin_packs/vanilla/skins.json", Some("classic_skins_json")),
+            ("assets/gui/dist/hbui/index.html", Some("hbui_pack")),
+            ("assets/renderer/materials/RenderChunk.material.bin", Some("no_fog_materials")),
+            ("assets/renderer/materials/UIText.material.bin", Some("renderer_pack")),
+            ("assets/resource_packs/vanilla/cameras/third_person_front.json", Some("third_person_front_camera")),
+            ("assets/resource_packs/vanilla/cameras/free.json", Some("cameras_pack")),
+            ("assets/resource_packs/vanilla/texts/loading_messages.json", Some("loading_messages")),
+            ("assets/resource_packs/vanilla/textures/environment/clouds.png", Some("java_clouds")),
+            ("assets/resource_packs/vanilla/textures/environment/sun.png", None),
+        ];
+        for (path, expected) in corpus {
+            assert_eq!(rule_for(path, &on, true), expected, "{path}");
+        }
+    }
+
+    #[test]
+    fn pack_mappings_get_trailing_slashes() {
+        let mut mapping = PackMapping::new("test", "assets/gui/dist/hbui", "/hbui");
+        assert!(mapping.normalize());
+        assert_eq!((mapping.apk.as_str(), mapping.pack.as_str()), ("gui/dist/hbui/", "hbui/"));
+        let mut root = PackMapping::new("test", "renderer/", "");
+        assert!(root.normalize());
+        assert_eq!((root.apk.as_str(), root.pack.as_str()), ("renderer/", ""));
+        assert!(!PackMapping::new("test", "assets/", "x").normalize());
+        for mut mapping in default_pack_mappings() {
+            let (apk, pack) = (mapping.apk.clone(), mapping.pack.clone());
+            assert!(mapping.normalize());
+            assert_eq!((mapping.apk, mapping.pack), (apk, pack));
+        }
+    }
+
+    #[test]
+    fn configured_mappings_merge_by_name() {
+        let mut renderer = PackMapping::new("renderer_pack", "renderer/", "renderer/");
+        renderer.convert_materials = false;
+        let extra = PackMapping::new("sounds_pack", "sounds/", "sounds/");
+        let mappings = with_default_mappings(vec![extra, renderer]);
+        let names: Vec<&str> = mappings.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["hbui_pack", "persona_pack", "renderer_pack", "cameras_pack", "sounds_pack"]);
+        assert!(!mappings[2].convert_materials);
+    }
+
+    #[test]
+    fn java_cubemap_wins_over_no_fog() {
+        let cubemap = "assets/renderer/materials/LegacyCubemap.material.bin";
+        let mut config = all_features();
+        assert_eq!(rule_for(cubemap, &config, true), Some("java_cubemap"));
+        config.java_cubemap = false;
+        assert_eq!(rule_for(cubemap, &config, true), Some("no_fog_materials"));
+        config.no_fog = false;
+        assert_eq!(rule_for(cubemap, &config, true), Some("renderer_pack"));
+    }
+
+    #[test]
+    fn patches_need_the_original() {
+        let rule = rule!("test_patch", PATCH, Matcher::Prefix("resource_packs/"), |_| true => Action::Patch(|_, data| Ok(data.to_vec())));
+        let path = AssetPath::new("resource_packs/vanilla/blocks.json").unwrap();
+        let config = no_features();
+        assert!(rule.applies(&path, &config, true));
+        assert!(!rule.applies(&path, &config, false));
+    }
+
+    #[test]
+    fn redirects_split_off_the_apk_folder() {
+        init_test_config();
+        let path = AssetPath::new("assets/renderer/materials/UIText.material.bin").unwrap();
+        let rule = find_rule(&path, &no_features(), true).unwrap();
+        let (mapping, file) = rule.redirect(&path).unwrap();
+        assert_eq!(mapping.pack, "renderer/");
+        assert_eq!(file, "materials/UIText.material.bin");
+        let splashes = find_rule(&AssetPath::new("resource_packs/vanilla/splashes.json").unwrap(), &no_features(), true);
+        assert!(splashes.unwrap().redirect(&path).is_none());
+    }
+}
@@ -0,0 +1,228 @@
+//! Text patches for the shader sources inside material.bin files.
+//!
+//! Files in `origin_mods/shader_patches/` are loaded as a [`PatchSet`], each
+//! patch names a material file, optionally a pass, a stage and a platform
+//! prefix, and a list of edits:
+//!
+//! ```json
+//! [{
+//!     "material": "RenderChunk.material.bin",
+//!     "pass": "Transparent",
+//!     "stage": "Fragment",
+//!     "edits": [
+//!         { "op": "replace", "find": "fogColor.a", "with": "0.0" },
+//!         { "op": "regex", "pattern": "fogControl\\.[xy]", "with": "1000.0" },
+//!         { "op": "insert_before_main", "code": "#define NO_FOG" }
+//!     ]
+//! }]
+//! ```
+//!
+//! Patches run on vanilla and pack materials alike, the result is written
+//! back in the detected game version.
+use crate::patch_set::PatchSet;
+use materialbin::{bgfx_shader::BgfxShader, CompiledMaterialDefinition};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use scroll::Pread;
+use serde::Deserialize;
+use std::sync::OnceLock;
+
+#[derive(Deserialize)]
+struct ShaderPatch {
+    /// File name of the material, like `RenderChunk.material.bin`
+    material: String,
+    /// Every pass when left out
+    pass: Option<String>,
+    /// Every stage when left out
+    stage: Option<String>,
+    /// Prefix of the platform names to touch
+    #[serde(default = "default_platform")]
+    platform: String,
+    edits: Vec<Edit>,
+}
+
+fn default_platform() -> String {
+    "ESSL".to_owned()
+}
+
+#[derive(Deserialize)]
+#[serde(try_from = "EditSpec")]
+enum Edit {
+    Replace { find: String, with: String },
+    Regex { regex: Regex, with: String },
+    InsertBeforeMain { code: String },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum EditSpec {
+    Replace {
+        find: String,
+        with: String,
+    },
+    /// `with` can use `$1` style groups
+    Regex {
+        pattern: String,
+        with: String,
+    },
+    InsertBeforeMain {
+        code: String,
+    },
+}
+
+impl TryFrom<EditSpec> for Edit {
+    type Error = regex::Error;
+
+    fn try_from(spec: EditSpec) -> Result<Self, Self::Error> {
+        Ok(match spec {
+            EditSpec::Replace { find, with } => Edit::Replace { find, with },
+            EditSpec::Regex { pattern, with } => Edit::Regex {
+                regex: Regex::new(&pattern)?,
+                with,
+            },
+            EditSpec::InsertBeforeMain { code } => Edit::InsertBeforeMain { code },
+        })
+    }
+}
+
+// The entry point itself, not `mainImage` or other functions starting with main
+static MAIN: Lazy<Regex> = Lazy::new(|| Regex::new(r"\bvoid\s+main\s*\(").unwrap());
+
+impl Edit {
+    // None when the edit does not apply to this source
+    fn apply(&self, source: &str) -> Option<String> {
+        match self {
+            Edit::Replace { find, with } => source
+                .contains(find.as_str())
+                .then(|| source.replace(find.as_str(), with)),
+            Edit::Regex { regex, with } => regex
+                .is_match(source)
+                .then(|| regex.replace_all(source, with.as_str()).into_owned()),
+            Edit::InsertBeforeMain { code } => MAIN.find(source).map(|main| {
+                let at = main.start();
+                let mut edited = String::with_capacity(source.len() + code.len() + 1);
+                edited.push_str(&source[..at]);
+                edited.push_str(code);
+                edited.push('\n');
+                edited.push_str(&source[at..]);
+                edited
+            }),
+        }
+    }
+}
+
+static PATCHES: OnceLock<PatchSet<ShaderPatch>> = OnceLock::new();
+
+pub fn init_shader_patches() {
+    let _ = PATCHES.set(PatchSet::load("shader_patches", |patch| &patch.material));
+}
+
+/// Whether any shader patch targets this material, takes any path to it
+pub fn has_patches(name: &str) -> bool {
+    PATCHES.get().is_some_and(|set| set.get(name).is_some())
+}
+
+pub fn fingerprint() -> Option<&'static str> {
+    PATCHES.get().and_then(PatchSet::fingerprint)
+}
+
+/// Applies the patches for `name` to `material`, returns whether anything changed
+pub fn patch_material(name: &str, material: &mut CompiledMaterialDefinition) -> bool {
+    let Some(patches) = PATCHES.get().and_then(|set| set.get(name)) else {
+        return false;
+    };
+    let mut changed = 0;
+    for patch in patches {
+        // Shaders each edit changed, to warn about edits that never match
+        let mut hits = vec![0; patch.edits.len()];
+        for (pass_name, pass) in material.passes.iter_mut() {
+            if patch
+                .pass
+                .as_ref()
+                .is_some_and(|wanted| wanted != pass_name)
+            {
+                continue;
+            }
+            for variant in &mut pass.variants {
+                for (stage, code) in variant.shader_codes.iter_mut() {
+                    let wanted = patch.stage.as_ref().is_none_or(|s| *s == stage.stage_name)
+                        && stage.platform_name.starts_with(&patch.platform);
+                    if wanted && patch_shader(&patch.edits, &mut hits, &mut code.bgfx_shader_data) {
+                        changed += 1;
+                    }
+                }
+            }
+        }
+        for (index, _) in hits.iter().enumerate().filter(|(_, &hits)| hits == 0) {
+            log::warn!("[shader] Edit {index} for {name} matched nothing");
+        }
+    }
+    if changed > 0 {
+        log::info!("[shader] {name}: patched {changed} shaders");
+    }
+    changed > 0
+}
+
+// Runs the edits over the source in a bgfx shader blob and rewrites it
+fn patch_shader(edits: &[Edit], hits: &mut [usize], data: &mut Vec<u8>) -> bool {
+    let Ok(mut shader) = data.pread::<BgfxShader>(0) else {
+        return false;
+    };
+    // Binary code (spir-v and the like) can't be edited as text
+    let Ok(mut source) = String::from_utf8(std::mem::take(&mut shader.code)) else {
+        return false;
+    };
+    let mut changed = false;
+    for (edit, hits) in edits.iter().zip(hits) {
+        if let Some(edited) = edit.apply(&source) {
+            source = edited;
+            *hits += 1;
+            changed = true;
+        }
+    }
+    if !changed {
+        return false;
+    }
+    shader.code = source.into_bytes();
+    let mut output = Vec::with_capacity(data.len());
+    if let Err(e) = shader.write(&mut output) {
+        log::warn!("[shader] Failed to write patched shader: {e}");
+        return false;
+    }
+    *data = output;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edit(json: &str) -> Edit {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn replace_needs_a_match() {
+        let edit = edit(r#"{ "op": "replace", "find": "fogColor.a", "with": "0.0" }"#);
+        assert_eq!(edit.apply("x = fogColor.a * fogColor.a;").unwrap(), "x = 0.0 * 0.0;");
+        assert!(edit.apply("x = fogColor.rgb;").is_none());
+    }
+
+    #[test]
+    fn regex_uses_groups() {
+        let edit = edit(r#"{ "op": "regex", "pattern": "fogControl\\.([xy])", "with": "fog_$1" }"#);
+        assert_eq!(edit.apply("fogControl.x + fogControl.y").unwrap(), "fog_x + fog_y");
+        assert!(edit.apply("fogControl.z").is_none());
+    }
+
+    #[test]
+    fn inserts_before_main_only() {
+        let edit = edit(r##"{ "op": "insert_before_main", "code": "#define NO_FOG" }"##);
+        let source = "void mainImage() {}\nvoid main ( ) {}\n";
+        assert_eq!(
+            edit.apply(source).unwrap(),
+            "void mainImage() {}\n#define NO_FOG\nvoid main ( ) {}\n"
+        );
+        assert!(edit.apply("void mainImage() {}").is_none());
+    }
+}
@@ -0,0 +1,40 @@
+//! Drops shaders for platforms the device can never use.
+//!
+//! Materials carry every variant for Direct3D, Metal, Vulkan and so on next
+//! to ESSL, while the game on Android only ever runs ESSL. With
+//! `strip_foreign_shaders` on, everything else is removed before a material
+//! is written back, so it is smaller to parse and to keep around.
+use materialbin::CompiledMaterialDefinition;
+
+// Every ESSL flavour is kept (ESSL_100 and ESSL_310), which one gets
+// used depends on the GPU and is only known to the game once it is running
+const KEPT_PLATFORM: &str = "ESSL";
+
+/// Removes foreign shaders from `material`, returns whether anything was removed
+pub fn strip_foreign(name: &str, material: &mut CompiledMaterialDefinition) -> bool {
+    let (mut shaders, mut bytes) = (0, 0);
+    for pass in material.passes.values_mut() {
+        for variant in &mut pass.variants {
+            let foreign: Vec<_> = variant
+                .shader_codes
+                .keys()
+                .filter(|stage| !stage.platform_name.starts_with(KEPT_PLATFORM))
+                .cloned()
+                .collect();
+            // A variant without any ESSL code is left alone rather than emptied
+            if foreign.len() == variant.shader_codes.len() {
+                continue;
+            }
+            for stage in foreign {
+                if let Some(code) = variant.shader_codes.remove(&stage) {
+                    shaders += 1;
+                    bytes += code.bgfx_shader_data.len();
+                }
+            }
+        }
+    }
+    if shaders > 0 {
+        log::info!("[strip] {name}: dropped {shaders} foreign shaders, {bytes} bytes saved");
+    }
+    shaders > 0
+}
@@ -0,0 +1,147 @@
+//! Optional record of every `AAssetManager_open` call.
+//!
+//! Enabled with `trace_assets`, each session appends JSON Lines to
+//! `origin_mods/trace/session_<unix time>.jsonl` from a background thread,
+//! and once more when the process exits.
+//! When disabled nothing is set up and the hook only checks a `OnceLock`.
+use crate::config::{ModConfig, CONFIG_DIR};
+use serde::Serialize;
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    mem,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Who ended up providing an opened asset
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Served {
+    /// The apk file, untouched
+    Vanilla,
+    /// Bytes built into the mod
+    Embedded,
+    /// The apk file after patching, also when a rule fell back to it
+    Patched,
+    /// `origin_mods/overrides/`
+    Override,
+    /// `origin_mods/packs/`
+    Archive,
+    /// An active resource pack
+    ResourcePack,
+    Blocked,
+    /// Not in the apk and nothing provided it
+    Missing,
+}
+
+#[derive(Serialize)]
+pub struct TraceEvent<'a> {
+    pub path: &'a str,
+    pub mode: &'static str,
+    pub existed: bool,
+    pub served: Served,
+    pub rule: Option<&'static str>,
+    pub size: Option<u64>,
+    pub micros: u64,
+}
+
+struct Tracer {
+    file: PathBuf,
+    pending: Mutex<Vec<u8>>,
+}
+
+static TRACER: OnceLock<Tracer> = OnceLock::new();
+
+pub fn init_tracer(config: &ModConfig) {
+    if !config.trace_assets {
+        return;
+    }
+    let dir = Path::new(CONFIG_DIR).join("trace");
+    if let Err(e) = fs::create_dir_all(&dir) {
+        log::warn!("Failed to create trace directory, tracing is off: {e}");
+        return;
+    }
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let file = dir.join(format!("session_{started}.jsonl"));
+    log::info!("Tracing asset opens to {}", file.display());
+    let _ = TRACER.set(Tracer {
+        file,
+        pending: Mutex::new(Vec::new()),
+    });
+
+    let interval = Duration::from_secs(config.trace_flush_secs.max(1));
+    let spawned = thread::Builder::new()
+        .name("origin-tracer".into())
+        .spawn(move || loop {
+            thread::sleep(interval);
+            flush();
+        });
+    if let Err(e) = spawned {
+        log::warn!("Failed to start trace flush thread: {e}");
+    }
+    // Whatever came in since the last interval would be lost otherwise
+    if unsafe { libc::atexit(flush_at_exit) } != 0 {
+        log::warn!("Failed to flush the trace at exit");
+    }
+}
+
+extern "C" fn flush_at_exit() {
+    flush();
+}
+
+#[inline]
+pub fn is_enabled() -> bool {
+    TRACER.get().is_some()
+}
+
+pub fn record(event: &TraceEvent) {
+    let Some(tracer) = TRACER.get() else {
+        return;
+    };
+    let mut pending = tracer.pending.lock().unwrap_or_else(|e| e.into_inner());
+    if let Err(e) = serde_json::to_writer(&mut *pending, event) {
+        log::warn!("Failed to serialize trace event: {e}");
+        return;
+    }
+    pending.push(b'\n');
+}
+
+/// Microseconds since `start`, for [`TraceEvent::micros`]
+pub fn micros_since(start: Instant) -> u64 {
+    start.elapsed().as_micros().try_into().unwrap_or(u64::MAX)
+}
+
+/// Name of an `AASSET_MODE_*` value
+pub fn mode_name(mode: libc::c_int) -> &'static str {
+    match mode {
+        0 => "unknown",
+        1 => "random",
+        2 => "streaming",
+        3 => "buffer",
+        _ => "invalid",
+    }
+}
+
+fn flush() {
+    let Some(tracer) = TRACER.get() else {
+        return;
+    };
+    // Don't hold the lock while writing so opens never wait on storage
+    let lines = mem::take(&mut *tracer.pending.lock().unwrap_or_else(|e| e.into_inner()));
+    if lines.is_empty() {
+        return;
+    }
+    let written = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&tracer.file)
+        .and_then(|mut file| file.write_all(&lines));
+    if let Err(e) = written {
+        log::warn!("Failed to write asset trace: {e}");
+    }
+}